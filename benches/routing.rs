@@ -0,0 +1,86 @@
+//! Var route lookup: compiled route tree vs. building regexes per request
+//!
+//! `cargo bench --bench routing`
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use saaba::{App, HTTPMethod, Request, Response};
+
+const ROUTES: usize = 50;
+
+fn patterns() -> Vec<String> {
+    (0..ROUTES)
+        .map(|i| format!("/api/resource{i}/{{id}}/items/{{item}}"))
+        .collect()
+}
+
+/// Lookup as done before the route tree: regexes are built on every call
+fn regex_lookup<'a>(patterns: &'a [String], url: &'a str) -> Option<HashMap<String, &'a str>> {
+    let re_replacement = Regex::new(r"\{(?<v>\w+)\}").unwrap();
+
+    for pattern in patterns {
+        let re_string = re_replacement
+            .replace_all(pattern, r"(?<$v>\w+)")
+            .into_owned();
+        let re_url = Regex::new(&re_string).unwrap();
+
+        if let Some(cap) = re_url.captures(url) {
+            return Some(
+                re_url
+                    .capture_names()
+                    .flatten()
+                    .filter_map(|n| Some((n.to_string(), cap.name(n)?.as_str())))
+                    .collect(),
+            );
+        }
+    }
+
+    None
+}
+
+fn measure(name: &str, iterations: u32, mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+
+    for _ in 0..iterations {
+        f();
+    }
+
+    let per_iter = start.elapsed() / iterations;
+    println!("{name:<12} {per_iter:>12?} / lookup");
+    per_iter
+}
+
+fn main() {
+    let patterns = patterns();
+
+    let mut app = App::new();
+    for pattern in &patterns {
+        app.route_var(HTTPMethod::GET, pattern, |_, vars| {
+            Response::from(vars.len().to_string())
+        });
+    }
+
+    // Worst case for the linear scan: the last registered route
+    let url = format!("/api/resource{}/42/items/7", ROUTES - 1);
+
+    let mut request = Request::new();
+    request.path = url.clone();
+
+    println!("{ROUTES} var routes");
+
+    // Compiling regexes is slow enough that a few rounds give a stable figure
+    let before = measure("regex", 20, || {
+        black_box(regex_lookup(black_box(&patterns), black_box(&url)));
+    });
+    let after = measure("route tree", 100_000, || {
+        black_box(app.handle(black_box(request.clone())));
+    });
+
+    println!(
+        "speedup      {:>11.1}x",
+        before.as_secs_f64() / after.as_secs_f64()
+    );
+}
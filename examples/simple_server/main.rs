@@ -1,17 +1,33 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
 
-use saaba::{App, HTTPMethod, Response};
+use saaba::{App, HTTPMethod, ParamError, Response, Router};
 
 const STATIC_DIR: &str = "./examples/simple_server/__static";
 
+#[derive(Default)]
+struct Visits(AtomicU64);
+
 fn main() {
     colog::default_builder()
         .filter(None, log::LevelFilter::Debug)
         .init();
 
     let mut app = App::new();
+    app.with_state(Visits::default());
+
+    app.middleware(|req, next| {
+        let started = Instant::now();
+        let path = req.path.clone();
 
-    app.route("get", "/", |req| {
+        let response = next.run(req);
+        log::info!("{} {} in {:?}", path, response.status, started.elapsed());
+
+        response
+    });
+
+    app.route("GET", "/", |req| {
         let req_addr = req.headers.get("Host").unwrap();
         let url = req.url;
 
@@ -24,9 +40,15 @@ fn main() {
         Response::html(content)
     });
 
-    app.get("/favicon.ico", |_| {
-        let favicon = format!("{STATIC_DIR}/favicon.ico");
-        Response::file(&favicon).with_header("Content-Type", "image/x-icon")
+    app.get("/favicon.ico", |_| Response::file(format!("{STATIC_DIR}/favicon.ico")));
+
+    app.post("/echo", |req| {
+        let text = req.text().unwrap_or("<binary>");
+
+        // Sent back as plain text, so that a posted page is never rendered
+        Response::from(format!("Received: {text}"))
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_header("X-Content-Type-Options", "nosniff")
     });
 
     app.route_var(HTTPMethod::GET, "/var/{variable}", |_, variables: HashMap<&str, &str>| {
@@ -40,6 +62,23 @@ fn main() {
         Response::html(content)
     });
 
+    app.get_var("/user/{id:uint}", |req, _| -> Result<Response, ParamError> {
+        let id = req.param::<u64>("id")?;
+
+        Ok(Response::html(format!("User <code>{id}</code>")))
+    });
+
+    app.get("/visits", |req| {
+        let visits = req.state::<Visits>().unwrap();
+        let count = visits.0.fetch_add(1, Ordering::Relaxed) + 1;
+
+        Response::html(format!("Visits: <code>{count}</code>"))
+    });
+
+    let mut api = Router::new();
+    api.get("/health", |_| "ok");
+    app.mount("/api", api);
+
     app.static_("/static", STATIC_DIR);
 
     app.run("0.0.0.0", 80).expect("Error");
@@ -1,37 +1,84 @@
 use std::{
     collections::HashMap,
-    fs,
-    io::{self, prelude::*, BufReader},
+    fmt, fs,
+    io::{self, prelude::*, BufReader, BufWriter},
     net::{TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
     path::Path,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::mime::guess_mime;
+use crate::constants::CRLF;
+use crate::mime::MimeTypes;
 
+use super::conditional::Conditions;
 use super::header::Header;
-use super::utils::parse_headers;
-use super::{HTTPMethod, Request, Response};
+use super::middleware::{Middleware, Next};
+use super::pool::{Backpressure, Queued, WorkerPool};
+use super::request::{RequestError, RequestLine};
+use super::response_error::{HandlerResult, ResponseError};
+use super::router::{route_shorthands, Endpoint, HandlerOutput, Matched, Router};
+use super::static_files::StaticOptions;
+use super::utils::{find_header, normalize_path, parse_headers, parse_query, percent_encode};
+use super::{Extensions, HTTPMethod, IntoResponse, Request, Response, ResponseCode};
 
-type HandlerKey = (HTTPMethod, String);
-type ExactHandler = Box<dyn Fn(Request) -> Response + 'static>;
-type VarHandler = Box<dyn Fn(Request, HashMap<&str, &str>) -> Response + 'static>;
+/// Longest request line or header line, line ending included
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+
+/// Largest set of header lines, and of trailer lines
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Pause after a failed accept, so that a lasting error does not spin
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// How often an idle connection checks whether others wait for its worker
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+type PanicHandler = Box<dyn Fn(&Request) -> Response + Send + Sync + 'static>;
+type ErrorHandler = Box<dyn Fn(&Request, &dyn ResponseError) -> Response + Send + Sync + 'static>;
 
 pub struct App {
-    exact_handlers: HashMap<HandlerKey, ExactHandler>,
-    var_handlers: HashMap<HandlerKey, VarHandler>,
-    static_handlers: HashMap<String, String>,
+    router: Router,
+    middlewares: Vec<Middleware>,
+    state: Arc<Extensions>,
+    mime_types: MimeTypes,
+    panic_handler: Option<PanicHandler>,
+    error_handler: Option<ErrorHandler>,
+    workers: usize,
+    queue_size: usize,
+    backpressure: Backpressure,
+    keep_alive_timeout: Duration,
+    request_timeout: Duration,
+    write_timeout: Duration,
+    max_requests_per_connection: usize,
+    max_body_size: usize,
 }
 
 impl App {
     pub fn new() -> Self {
         Self {
-            exact_handlers: HashMap::new(),
-            var_handlers: HashMap::new(),
-            static_handlers: HashMap::new(),
+            router: Router::new(),
+            middlewares: Vec::new(),
+            state: Arc::new(Extensions::new()),
+            mime_types: MimeTypes::new(),
+            panic_handler: None,
+            error_handler: None,
+            workers: thread::available_parallelism().map_or(16, |n| (n.get() * 4).max(16)),
+            queue_size: 64,
+            backpressure: Backpressure::default(),
+            keep_alive_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            max_requests_per_connection: 100,
+            max_body_size: 16 * 1024 * 1024,
         }
     }
 
-    pub fn route<HTTPMethodLike, HandlerFunctionLike>(
+    /// See `Router::route`
+    #[track_caller]
+    pub fn route<HTTPMethodLike, HandlerFunctionLike, R>(
         &mut self,
         method: HTTPMethodLike,
         url: &str,
@@ -39,23 +86,17 @@ impl App {
     ) -> &mut Self
     where
         HTTPMethodLike: TryInto<HTTPMethod> + std::fmt::Debug,
-        HandlerFunctionLike: Fn(Request) -> Response + 'static,
+        HandlerFunctionLike: Fn(Request) -> R + Send + Sync + 'static,
+        R: HandlerResult,
         <HTTPMethodLike as TryInto<HTTPMethod>>::Error: std::fmt::Display,
     {
-        let boxed_cb = Box::new(callback);
-        let http_method: HTTPMethod = method
-            .try_into()
-            .inspect_err(|e| log::error!("{}", e))
-            .unwrap_or_default();
-
-        let key: (HTTPMethod, String) = (http_method, url.to_owned());
-
-        self.exact_handlers.insert(key, boxed_cb);
-
+        self.router.route(method, url, callback);
         self
     }
 
-    pub fn route_var<HTTPMethodLike, VarHandlerFunctionLike>(
+    /// See `Router::route_var`
+    #[track_caller]
+    pub fn route_var<HTTPMethodLike, VarHandlerFunctionLike, R>(
         &mut self,
         method: HTTPMethodLike,
         url: &str,
@@ -63,72 +104,151 @@ impl App {
     ) -> &mut Self
     where
         HTTPMethodLike: Into<HTTPMethod>,
-        VarHandlerFunctionLike: Fn(Request, HashMap<&str, &str>) -> Response + 'static,
+        VarHandlerFunctionLike: Fn(Request, HashMap<&str, &str>) -> R + Send + Sync + 'static,
+        R: HandlerResult,
     {
-        let boxed_cb = Box::new(callback);
-        let key: (HTTPMethod, String) = (method.into(), url.to_owned());
+        self.router.route_var(method, url, callback);
+        self
+    }
 
-        self.var_handlers.insert(key, boxed_cb);
+    // Shorthands
+
+    route_shorthands!();
 
+    pub fn static_(&mut self, url: &str, dest: &str) -> &mut Self {
+        self.router.static_(url, dest);
         self
     }
 
-    // Shorthands
+    pub fn static_with(&mut self, url: &str, dest: &str, options: StaticOptions) -> &mut Self {
+        self.router.static_with(url, dest, options);
+        self
+    }
 
-    pub fn get<HandlerFunctionLike>(
-        &mut self,
-        url: &str,
-        callback: HandlerFunctionLike,
-    ) -> &mut Self
-    where
-        HandlerFunctionLike: Fn(Request) -> Response + 'static,
-    {
-        self.route(HTTPMethod::GET, url, callback)
+    /// See `Router::mount`
+    pub fn mount(&mut self, prefix: &str, router: Router) -> &mut Self {
+        self.router.mount(prefix, router);
+        self
+    }
+
+    /// Serve files ending in `.ext` as `mime`, before the built-in table.
+    /// Extensions may span several dots: `app.mime_type("mjs.map", "application/json")`
+    pub fn mime_type(&mut self, ext: &str, mime: &str) -> &mut Self {
+        self.mime_types.insert(ext, mime);
+        self
     }
 
-    pub fn post<HandlerFunctionLike>(
+    /// Load extension mappings from a file in the `/etc/mime.types` format
+    pub fn load_mime_types(&mut self, path: impl AsRef<Path>) -> Result<&mut Self, io::Error> {
+        let source = fs::read_to_string(path)?;
+        self.mime_types.parse(&source);
+        Ok(self)
+    }
+
+    /// Run `callback` around every request, before routing. Unlike
+    /// `Router::middleware`, it also sees requests answered by the server
+    /// itself, such as `404 Not Found` or `OPTIONS`
+    pub fn middleware<MiddlewareFunctionLike, R>(
         &mut self,
-        url: &str,
-        callback: HandlerFunctionLike,
+        callback: MiddlewareFunctionLike,
     ) -> &mut Self
     where
-        HandlerFunctionLike: Fn(Request) -> Response + 'static,
+        MiddlewareFunctionLike: Fn(Request, Next) -> R + Send + Sync + 'static,
+        R: IntoResponse,
     {
-        self.route(HTTPMethod::POST, url, callback)
+        self.middlewares.push(Box::new(move |req, next| {
+            callback(req, next).into_response()
+        }));
+        self
+    }
+
+    /// Share a value with every handler through `Request::state`.
+    /// One value per type; wrap it in your own type to keep several of the same
+    pub fn with_state<T: Send + Sync + 'static>(&mut self, state: T) -> &mut Self {
+        Arc::make_mut(&mut self.state).insert(state);
+        self
     }
 
-    pub fn get_var<VarHandlerFunctionLike>(
+    /// Response sent when a handler panics, instead of the default error page
+    pub fn panic_handler<PanicHandlerFunctionLike>(
         &mut self,
-        url: &str,
-        callback: VarHandlerFunctionLike,
+        callback: PanicHandlerFunctionLike,
     ) -> &mut Self
     where
-        VarHandlerFunctionLike: Fn(Request, HashMap<&str, &str>) -> Response + 'static,
+        PanicHandlerFunctionLike: Fn(&Request) -> Response + Send + Sync + 'static,
     {
-        self.route_var(HTTPMethod::GET, url, callback)
+        self.panic_handler = Some(Box::new(callback));
+        self
     }
 
-    pub fn post_var<VarHandlerFunctionLike>(
+    /// Response sent when a handler returns an error, instead of
+    /// `ResponseError::error_response`
+    pub fn error_handler<ErrorHandlerFunctionLike>(
         &mut self,
-        url: &str,
-        callback: VarHandlerFunctionLike,
+        callback: ErrorHandlerFunctionLike,
     ) -> &mut Self
     where
-        VarHandlerFunctionLike: Fn(Request, HashMap<&str, &str>) -> Response + 'static,
+        ErrorHandlerFunctionLike:
+            Fn(&Request, &dyn ResponseError) -> Response + Send + Sync + 'static,
     {
-        self.route_var(HTTPMethod::POST, url, callback)
+        self.error_handler = Some(Box::new(callback));
+        self
     }
 
-    pub fn static_(&mut self, url: &str, dest: &str) -> &mut Self {
-        let mut url_ = url.to_string();
+    // Server
+
+    /// Number of threads handling connections. A connection holds its worker
+    /// while a request is read and answered, so this is the number of
+    /// clients served at once; idle keep-alive connections give theirs up
+    /// as soon as other connections are waiting
+    pub fn workers(&mut self, workers: usize) -> &mut Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Number of accepted connections waiting for a free worker
+    pub fn queue_size(&mut self, queue_size: usize) -> &mut Self {
+        self.queue_size = queue_size;
+        self
+    }
 
-        url_ = url_.trim_end_matches('/').to_string();
+    /// Behavior when the connection queue is full
+    pub fn backpressure(&mut self, backpressure: Backpressure) -> &mut Self {
+        self.backpressure = backpressure;
+        self
+    }
 
-        if !url_.starts_with('/') {
-            url_ = format!("/{url_}");
-        }
+    /// How long an idle connection is kept open waiting for the next request
+    pub fn keep_alive_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// Time allowed to receive a whole request, head and body. Slower
+    /// clients are answered with `408 Request Timeout`
+    pub fn request_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// How long writing a response may wait for the client to read. A client
+    /// that stops reading has its connection closed after that
+    pub fn write_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.write_timeout = timeout;
+        self
+    }
 
-        self.static_handlers.insert(url_, dest.to_string());
+    /// Number of requests served on one connection before closing it.
+    /// `1` disables keep-alive
+    pub fn max_requests_per_connection(&mut self, max_requests: usize) -> &mut Self {
+        self.max_requests_per_connection = max_requests;
+        self
+    }
+
+    /// Largest request body accepted, in bytes, chunked framing and trailers
+    /// included. Larger ones are answered with `413 Content Too Large`
+    pub fn max_body_size(&mut self, max_body_size: usize) -> &mut Self {
+        self.max_body_size = max_body_size;
         self
     }
 
@@ -136,194 +256,658 @@ impl App {
         let addr = format!("{hostname}:{port}");
         let listener = TcpListener::bind(addr)?;
 
-        for stream in listener.incoming() {
-            let stream = stream?;
+        thread::scope(|scope| {
+            let pool = WorkerPool::new(
+                scope,
+                self.workers,
+                self.queue_size,
+                self.backpressure,
+                |stream, queued| self.handle_connection(stream, queued),
+            );
 
-            self.handle_connection(stream);
-        }
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) if is_fatal_accept_error(&e) => return Err(e),
+                    Err(e) => {
+                        // Out of file descriptors, or a client gone before being accepted
+                        log::warn!("Failed to accept a connection: {}", e);
+                        thread::sleep(ACCEPT_RETRY_DELAY);
+                        continue;
+                    }
+                };
+
+                if let Err(mut stream) = pool.dispatch(stream) {
+                    log::warn!("Connection queue is full, rejecting");
+
+                    // The accept loop must not wait on a client that does not read
+                    stream.set_write_timeout(Some(self.write_timeout)).ok();
+
+                    let response = Response::error(ResponseCode::ServiceUnavailable)
+                        .with_header(Header::Connection, "close");
+                    response.write_to(&mut stream).ok();
+                }
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 
-    fn try_find_exact(&self, request: &Request) -> Option<Response> {
-        let method = request.method;
-        let url = request.url.clone();
-
-        let handler_option: Option<&ExactHandler> = self.exact_handlers.get(&(method, url));
+    /// Route a request without a network connection. Responses to HEAD keep
+    /// their body, which is only left out when writing to a connection
+    pub fn handle(&self, request: Request) -> Response {
+        self.find_response(request)
+    }
 
-        handler_option.map(|handler| handler(request.clone()))
+    /// Answer a matched request with its endpoint, through the middleware
+    /// of the routers on the way
+    fn call(&self, request: &Request, matched: Matched) -> Response {
+        let mut routed = request.clone();
+        routed.path = matched.path;
+        routed.params = matched.params;
+
+        let route = matched.route;
+        let endpoint = matched.endpoint;
+        let call_endpoint = |request| self.call_endpoint(request, &route, &endpoint);
+
+        // Handler panics are caught closer, what reaches here comes from middleware
+        self.catch_panic(
+            request,
+            format_args!("Middleware for {} `{}`", request.method, route),
+            || Next::new(&matched.middlewares, &call_endpoint).run(routed),
+        )
     }
 
-    fn try_find_var(&self, request: &Request) -> Option<Response> {
-        let url = request.url.clone();
+    fn call_endpoint(&self, request: Request, route: &str, endpoint: &Endpoint) -> Response {
+        match endpoint {
+            Endpoint::Exact(handler) => {
+                self.call_guarded(&request, route, || handler(request.clone()))
+            }
+            Endpoint::Var(handler) => {
+                let params = request.params.clone();
+                let vars: HashMap<&str, &str> = params
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .collect();
 
-        let re_replacement = regex::Regex::new(r"\{(?<v>\w+)\}").unwrap();
+                self.call_guarded(&request, route, || handler(request.clone(), vars))
+            }
+            Endpoint::File(path) => self.file_response(path),
+            Endpoint::AddSlash => {
+                // Relative to the current path, so it holds under any mount prefix.
+                // Encoded again, the path was decoded for routing
+                let last_segment =
+                    percent_encode(request.path.rsplit('/').next().unwrap_or_default());
+
+                let location = match request.url.split_once('?') {
+                    Some((_, query)) => format!("{}/?{}", last_segment, query),
+                    None => format!("{}/", last_segment),
+                };
+
+                Response::redirect(location)
+            }
+            Endpoint::Refused(code) => Response::error(*code),
+        }
+    }
 
-        for k in self.var_handlers.keys() {
-            let re_string_semi = &k.1; // semi regex expression
-            let re_string = re_replacement
-                .replace_all(re_string_semi, r"(?<$v>\w+)")
-                .into_owned();
+    /// Run a handler, turning a returned error or a panic into an error response
+    fn call_guarded<F>(&self, request: &Request, route: &str, handler: F) -> Response
+    where
+        F: FnOnce() -> HandlerOutput,
+    {
+        let context = format_args!("Handler for {} `{}`", request.method, route);
 
-            let re_url = regex::Regex::new(&re_string).unwrap();
+        self.catch_panic(request, context, || match handler() {
+            Ok(response) => response,
+            Err(error) => {
+                let message = format!("{} failed: {}", context, error);
 
-            if re_url.is_match(&url) {
-                log::debug!("Found var handler: {}", re_string_semi);
+                match u32::from(error.status()) {
+                    500.. => log::error!("{}", message),
+                    _ => log::debug!("{}", message),
+                }
 
-                let cap = re_url.captures(&url).unwrap();
+                match &self.error_handler {
+                    Some(error_handler) => error_handler(request, error.as_ref()),
+                    None => error.error_response(),
+                }
+            }
+        })
+    }
 
-                let vars: HashMap<&str, &str> = re_url
-                    .capture_names()
-                    .flatten()
-                    .filter_map(|n| Some((n, cap.name(n)?.as_str())))
-                    .collect();
+    /// Run `f`, answering a panic with the panic handler
+    fn catch_panic<F>(&self, request: &Request, context: fmt::Arguments, f: F) -> Response
+    where
+        F: FnOnce() -> Response,
+    {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(response) => response,
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown panic");
+
+                log::error!("{} panicked: {}", context, message);
+
+                match &self.panic_handler {
+                    Some(panic_handler) => panic_handler(request),
+                    None => Response::error(ResponseCode::InternalServerError),
+                }
+            }
+        }
+    }
 
-                let handler = self.var_handlers.get(k).unwrap();
-                let res = handler(request.clone(), vars);
-                return Some(res);
+    /// A file of a static mount, with the registered MIME types taking over
+    fn file_response(&self, file_path: &Path) -> Response {
+        let mut response = Response::file(file_path);
+
+        if response.status == u32::from(ResponseCode::OK) {
+            if let Some(t) = self.mime_types.lookup(&file_path.to_string_lossy()) {
+                response.set_header(Header::ContentType, t);
             }
         }
-        None
+
+        response
+    }
+
+    /// Methods for which some handler or static mount answers `path`
+    fn allowed_methods(&self, path: &str) -> Vec<HTTPMethod> {
+        let mut methods = self.router.allowed_methods(path);
+
+        // Answered automatically
+        if methods.contains(&HTTPMethod::GET) {
+            methods.push(HTTPMethod::HEAD);
+        }
+
+        if !methods.is_empty() {
+            methods.push(HTTPMethod::OPTIONS);
+        }
+
+        methods.sort();
+        methods.dedup();
+        methods
+    }
+
+    /// `Allow` header value for a path, `None` if nothing answers it
+    fn allow_header(&self, path: &str) -> Option<String> {
+        let allowed = self.allowed_methods(path);
+
+        if allowed.is_empty() {
+            return None;
+        }
+
+        let allow = allowed
+            .iter()
+            .map(HTTPMethod::to_string)
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        Some(allow)
     }
 
-    fn url_starts_with(url: String, key: String) -> bool {
-        let url_seg = url.split("/").collect::<Vec<_>>();
-        let key_seg = key.split("/").collect::<Vec<_>>();
-        url_seg.starts_with(&key_seg) || key == "/"
+    fn try_method_not_allowed(&self, request: &Request) -> Option<Response> {
+        let allow = self.allow_header(&request.path)?;
+
+        Some(Response::error(ResponseCode::MethodNotAllowed).with_header(Header::Allow, allow))
     }
 
-    fn similarity(url: String, key: String) -> i32 {
-        let url_seg = url.split("/");
-        let key_seg = key.split("/");
+    fn try_options(&self, request: &Request) -> Option<Response> {
+        if request.method != HTTPMethod::OPTIONS {
+            return None;
+        }
+
+        // `OPTIONS *` asks about the server as a whole
+        if request.path == "*" {
+            return Some(Response::from_status(ResponseCode::NoContent));
+        }
+
+        let allow = self.allow_header(&request.path)?;
 
-        url_seg.zip(key_seg).take_while(|(u, k)| u == k).count() as i32
+        Some(Response::from_status(ResponseCode::NoContent).with_header(Header::Allow, allow))
     }
 
-    fn try_find_static(&self, request: &Request) -> Option<Response> {
-        let mut url = request.url.clone();
-        let keys = self.static_handlers.keys();
+    fn try_find_handler(&self, request: &Request) -> Option<Response> {
+        let matched = self.router.find(&request.method, &request.path)?;
 
-        // Find keys for current path
-        let mut keys: Vec<&String> = keys
-            .filter(|k| Self::url_starts_with(url.clone(), k.to_string()))
-            .collect();
+        Some(self.call(request, matched))
+    }
 
-        if keys.is_empty() {
+    /// HEAD falls back to the GET handler, sent without its body
+    fn try_head_as_get(&self, request: &Request) -> Option<Response> {
+        if request.method != HTTPMethod::HEAD {
             return None;
         }
 
-        keys.sort_by_key(|k| Self::similarity(url.to_string(), k.to_string()));
+        let mut get_request = request.clone();
+        get_request.method = HTTPMethod::GET;
 
-        let selected = keys.last().unwrap().to_string();
+        self.try_find_handler(&get_request)
+    }
 
-        if selected == "/" {
-            url.insert(0, '/');
+    /// Extension methods nobody registered are not implemented by the server,
+    /// nor is CONNECT: it is not a proxy
+    fn try_not_implemented(&self, request: &Request) -> Option<Response> {
+        if !matches!(
+            request.method,
+            HTTPMethod::Extension(_) | HTTPMethod::CONNECT
+        ) {
+            return None;
         }
 
-        let dest_option: Option<&String> = self.static_handlers.get(&selected);
+        match self.router.implements(&request.method) {
+            true => None,
+            false => Some(Response::error(ResponseCode::NotImplemented)),
+        }
+    }
 
-        if let Some(dest) = dest_option {
-            let resource_path_string = url.replacen(&selected, dest, 1);
-            let resource_path = Path::new(&resource_path_string);
-            log::debug!(
-                "Found static resource path `{}` on handler `{}`",
-                resource_path_string,
-                selected
-            );
+    fn respond(&self, request: Request) -> Response {
+        log::debug!("Seeking for handler: {}", &request.path);
+
+        self.try_not_implemented(&request)
+            .or_else(|| self.try_find_handler(&request))
+            .or_else(|| self.try_head_as_get(&request))
+            .or_else(|| self.try_options(&request))
+            .or_else(|| self.try_method_not_allowed(&request))
+            .unwrap_or(Response::not_found())
+    }
+
+    fn find_response(&self, mut request: Request) -> Response {
+        request.state = Arc::clone(&self.state);
+
+        let conditions = Conditions::from_request(&request);
+        let middlewares: Vec<&Middleware> = self.middlewares.iter().collect();
+        let respond = |request| self.respond(request);
+
+        // The panic handler only gets to see the head of the request
+        let body = std::mem::take(&mut request.body);
+        let head = request.clone();
+        request.body = body;
+
+        let mut response = self.catch_panic(
+            &head,
+            format_args!("Middleware for {} `{}`", head.method, head.path),
+            || Next::new(&middlewares, &respond).run(request),
+        );
+
+        if let Some(conditions) = conditions {
+            response = conditions.apply(response);
+        }
+
+        response
+    }
+
+    /// Read a line of at most `max_length` bytes, line ending included
+    fn read_line(
+        reader: &mut impl BufRead,
+        max_length: usize,
+        too_long: impl FnOnce() -> RequestError,
+    ) -> Result<Option<String>, RequestError> {
+        let mut line = Vec::new();
+
+        reader
+            .take(max_length as u64)
+            .read_until(b'\n', &mut line)?;
+
+        // Checked first, a limit of zero leaves no room for the line ending
+        if !line.ends_with(b"\n") && line.len() == max_length {
+            return Err(too_long());
+        }
+
+        if line.is_empty() {
+            return Ok(None);
+        }
+
+        let line = String::from_utf8(line)
+            .map_err(|_| RequestError::BadRequest("request head is not valid UTF-8".to_string()))?;
 
-            if !resource_path.exists() {
-                return None;
+        Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+    }
+
+    /// Read header lines up to the empty one, `max_size` bytes at most
+    fn read_head(
+        reader: &mut impl BufRead,
+        max_size: usize,
+        too_large: impl Fn() -> RequestError,
+    ) -> Result<Vec<String>, RequestError> {
+        let mut lines = Vec::new();
+        let mut remaining = max_size;
+
+        while let Some(line) = Self::read_line(reader, remaining.min(MAX_LINE_LENGTH), &too_large)?
+        {
+            if line.is_empty() {
+                break;
             }
 
-            if resource_path.is_file() {
-                return Self::try_read_file(resource_path_string);
+            remaining = remaining.saturating_sub(line.len() + CRLF.len());
+            lines.push(line);
+        }
+
+        Ok(lines)
+    }
+
+    fn read_body(
+        reader: &mut impl BufRead,
+        headers: &mut HashMap<String, String>,
+        max_body_size: usize,
+    ) -> Result<Vec<u8>, RequestError> {
+        let content_length = find_header(headers, Header::ContentLength).map(str::to_owned);
+        let transfer_encoding = find_header(headers, Header::TransferEncoding).map(str::to_owned);
+
+        match (content_length, transfer_encoding) {
+            // Both present is a request smuggling vector, refuse it
+            (Some(_), Some(_)) => Err(RequestError::BadRequest(
+                "both Content-Length and Transfer-Encoding are present".to_string(),
+            )),
+            (None, Some(encoding)) => {
+                let codings: Vec<String> = encoding
+                    .split(',')
+                    .map(|c| c.trim().to_ascii_lowercase())
+                    .collect();
+
+                match codings.as_slice() {
+                    [coding] if coding == "chunked" => {
+                        Self::read_chunked_body(reader, headers, max_body_size)
+                    }
+                    [.., last] if last == "chunked" => Err(RequestError::NotImplemented(format!(
+                        "unsupported transfer coding `{}`",
+                        encoding
+                    ))),
+                    _ => Err(RequestError::BadRequest(format!(
+                        "request body length cannot be determined from `{}`",
+                        encoding
+                    ))),
+                }
             }
+            (Some(value), None) => {
+                let value = value.trim();
+                let invalid =
+                    || RequestError::BadRequest(format!("invalid Content-Length `{}`", value));
 
-            if resource_path.is_dir() {
-                if !resource_path_string.ends_with("/") {
-                    log::debug!("Adding a slash to `{}` as it is a path", request.url);
-                    return Some(Response::redirect(request.url.clone() + "/"));
+                if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(invalid());
                 }
 
-                let resource_index_string = resource_path_string.clone() + "index.html";
-                let resource_index_path = Path::new(&resource_index_string);
+                // Too many digits for a `u64` is too large anyway
+                let length = value.parse::<u64>().unwrap_or(u64::MAX);
 
-                if resource_index_path.exists() && resource_index_path.is_file() {
-                    return Self::try_read_file(resource_index_string);
+                if length > max_body_size as u64 {
+                    return Err(RequestError::TooLarge(format!(
+                        "Content-Length {} exceeds {} bytes",
+                        value, max_body_size
+                    )));
+                }
+
+                // Grows with what actually arrives, not with what was announced
+                let mut body = Vec::new();
+                reader.take(length).read_to_end(&mut body)?;
+
+                match body.len() as u64 == length {
+                    true => Ok(body),
+                    false => Err(RequestError::Io(io::ErrorKind::UnexpectedEof.into())),
                 }
             }
+            (None, None) => Ok(Vec::new()),
         }
-
-        None
     }
 
-    fn try_read_file(file_path_string: String) -> Option<Response> {
-        //! file_path_string: path of **existing** file
+    /// Decode a chunked body. Everything read counts against `max_body_size`,
+    /// chunk size lines and trailers included
+    fn read_chunked_body(
+        reader: &mut impl BufRead,
+        headers: &mut HashMap<String, String>,
+        max_body_size: usize,
+    ) -> Result<Vec<u8>, RequestError> {
+        let limit = max_body_size as u64;
+        let too_large =
+            || RequestError::TooLarge(format!("chunked body exceeds {} bytes", max_body_size));
+
+        let mut body = Vec::new();
+        let mut received: u64 = 0;
+
+        loop {
+            let max_line = limit.saturating_sub(received).min(MAX_LINE_LENGTH as u64);
+            let line = Self::read_line(reader, max_line as usize, too_large)?
+                .ok_or(RequestError::Io(io::ErrorKind::UnexpectedEof.into()))?;
+
+            received += (line.len() + CRLF.len()) as u64;
+
+            // Chunk extensions (`;name=value`) carry nothing we use
+            let size_str = line.split(';').next().unwrap_or_default().trim();
+            let invalid = || RequestError::BadRequest(format!("invalid chunk size `{}`", size_str));
+
+            if size_str.is_empty() || !size_str.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+
+            // Too many digits for a `u64` is too large anyway
+            let size = u64::from_str_radix(size_str, 16).unwrap_or(u64::MAX);
 
-        let file_path = Path::new(&file_path_string);
+            if size == 0 {
+                break;
+            }
 
-        match fs::read(file_path) {
-            Ok(content) => {
-                let type_ = guess_mime(&file_path_string);
-                let mut res = Response::from_content_bytevec(content);
+            received = received
+                .checked_add(size)
+                .and_then(|received| received.checked_add(CRLF.len() as u64))
+                .filter(|&received| received <= limit)
+                .ok_or_else(too_large)?;
 
-                if let Some(t) = type_ {
-                    res.set_header(Header::ContentType, &t)
-                }
+            let start = body.len();
+            reader.take(size).read_to_end(&mut body)?;
 
-                Some(res)
+            if (body.len() - start) as u64 != size {
+                return Err(RequestError::Io(io::ErrorKind::UnexpectedEof.into()));
             }
-            Err(_) => {
-                log::error!("Cannot read file `{}`", file_path_string);
-                None
+
+            let mut crlf = [0; 2];
+            reader.read_exact(&mut crlf)?;
+
+            if &crlf != b"\r\n" {
+                return Err(RequestError::BadRequest(
+                    "chunk is not terminated by CRLF".to_string(),
+                ));
             }
         }
-    }
 
-    fn find_response(&self, request: Request) -> Response {
-        log::debug!("Seeking for handler: {}", &request.url);
+        // Trailer fields never override the ones sent in the head
+        let max_trailers = limit.saturating_sub(received).min(MAX_HEAD_SIZE as u64);
+        let trailers = parse_headers(Self::read_head(reader, max_trailers as usize, too_large)?)?;
 
-        self.try_find_exact(&request)
-            .or_else(|| self.try_find_var(&request))
-            .or_else(|| self.try_find_static(&request))
-            .unwrap_or(Response::not_found())
-    }
+        for (key, value) in trailers {
+            if find_header(headers, &key).is_none() {
+                headers.insert(key, value);
+            }
+        }
 
-    fn handle_connection(&self, mut stream: TcpStream) {
-        let buf_reader = BufReader::new(&mut stream);
-        let http_request: Vec<String> = buf_reader
-            .lines()
-            .map(|result| result.unwrap())
-            .take_while(|line| !line.is_empty())
-            .collect();
+        Ok(body)
+    }
 
-        if http_request.is_empty() {
-            return;
-        }
+    /// Read the next request on a connection, `None` once the client is done
+    fn read_request(
+        reader: &mut impl BufRead,
+        max_body_size: usize,
+    ) -> Result<Option<Request>, RequestError> {
+        let too_long =
+            || RequestError::UriTooLong(format!("request line exceeds {} bytes", MAX_LINE_LENGTH));
+
+        // Stray CRLFs between requests are ignored
+        let request_line = loop {
+            match Self::read_line(reader, MAX_LINE_LENGTH, too_long)? {
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+                None => return Ok(None),
+            }
+        };
 
         // Main header
-        let request_v = http_request[0].split(' ').collect::<Vec<&str>>();
-        let url = request_v[1].to_string();
+        let RequestLine {
+            method,
+            target: url,
+            version,
+        } = RequestLine::parse(&request_line)?;
 
-        let method_str = request_v[0];
+        let (raw_path, query) = match url.split_once('?') {
+            Some((path, query)) => (path, parse_query(query)),
+            None => (url.as_str(), HashMap::new()),
+        };
 
-        let method = HTTPMethod::try_from(method_str)
-            .inspect_err(|e| log::error!("{}", e))
-            .unwrap_or_default();
+        let path = match raw_path {
+            "*" => raw_path.to_string(),
+            _ if method == HTTPMethod::CONNECT && !raw_path.starts_with('/') => {
+                raw_path.to_string()
+            }
+            _ => normalize_path(raw_path).ok_or_else(|| {
+                RequestError::BadRequest(format!("cannot decode request path `{}`", raw_path))
+            })?,
+        };
 
-        let headers = parse_headers(http_request[1..].to_vec());
+        let mut headers = parse_headers(Self::read_head(reader, MAX_HEAD_SIZE, || {
+            RequestError::HeaderTooLarge(format!("headers exceed {} bytes", MAX_HEAD_SIZE))
+        })?)?;
+        let body = Self::read_body(reader, &mut headers, max_body_size)?;
 
-        let request = Request {
+        Ok(Some(Request {
             method,
-            url: url.clone(),
+            url,
+            version,
+            path,
+            query,
+            params: HashMap::new(),
             headers,
+            body,
+            ..Request::new()
+        }))
+    }
+
+    /// Whether the client allows the connection to stay open after this request
+    fn wants_keep_alive(request: &Request) -> bool {
+        let connection = request.header(Header::Connection).unwrap_or_default();
+        let has_token = |token: &str| {
+            connection
+                .split(',')
+                .any(|t| t.trim().eq_ignore_ascii_case(token))
         };
 
-        log::debug!("Request: {request:#?}");
+        match request.version.as_str() {
+            _ if has_token("close") => false,
+            "HTTP/1.1" => true,
+            _ => has_token("keep-alive"),
+        }
+    }
+
+    fn write_response(
+        stream: &TcpStream,
+        response: Response,
+        head_only: bool,
+        chunked: bool,
+    ) -> Result<(), io::Error> {
+        response
+            .write(&mut BufWriter::new(stream), head_only, chunked)
+            .inspect_err(|e| match is_timeout(e) {
+                true => log::debug!("Client stopped reading, closing the connection"),
+                false => log::error!("Failed to write response: {}", e),
+            })
+    }
+
+    /// Wait for the next request on an idle connection. Gives up after the
+    /// keep-alive timeout, or as soon as other connections wait for a worker
+    fn wait_for_request(
+        &self,
+        reader: &mut BufReader<DeadlineReader>,
+        queued: &Queued,
+    ) -> Result<bool, io::Error> {
+        let deadline = Instant::now() + self.keep_alive_timeout;
+
+        loop {
+            reader.get_mut().deadline = deadline.min(Instant::now() + IDLE_POLL_INTERVAL);
+
+            match reader.fill_buf() {
+                Ok(buf) => return Ok(!buf.is_empty()),
+                Err(e) if is_timeout(&e) && Instant::now() < deadline && queued.is_empty() => {}
+                Err(e) if is_timeout(&e) => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn handle_connection(&self, stream: TcpStream, queued: &Queued) {
+        if let Err(e) = stream.set_write_timeout(Some(self.write_timeout)) {
+            log::error!("Cannot set connection timeout: {}", e);
+        }
+
+        let mut buf_reader = BufReader::new(DeadlineReader {
+            stream: &stream,
+            deadline: Instant::now(),
+        });
+        let mut served = 0;
+
+        // Pipelined requests stay in `buf_reader` and are answered in order
+        loop {
+            if served > 0 && buf_reader.buffer().is_empty() {
+                match self.wait_for_request(&mut buf_reader, queued) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        log::debug!("Closing idle connection");
+                        break;
+                    }
+                    Err(e) => {
+                        log::error!("Failed to read request: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            buf_reader.get_mut().deadline = Instant::now() + self.request_timeout;
+
+            let request = match Self::read_request(&mut buf_reader, self.max_body_size) {
+                Ok(Some(request)) => request,
+                Ok(None) => break,
+                Err(RequestError::Io(e)) if is_timeout(&e) => {
+                    log::debug!("Request not received in time");
+                    let response = Response::error(ResponseCode::RequestTimeout)
+                        .with_header(Header::Connection, "close");
+                    Self::write_response(&stream, response, false, true).ok();
+                    break;
+                }
+                Err(RequestError::Io(e)) => {
+                    log::error!("Failed to read request: {}", e);
+                    break;
+                }
+                Err(e) => {
+                    log::error!("{}", e);
+                    let response = e.response().with_header(Header::Connection, "close");
+                    Self::write_response(&stream, response, false, true).ok();
+                    break;
+                }
+            };
+
+            log::debug!("Request: {request:#?}");
+
+            served += 1;
+            let mut keep_alive =
+                Self::wants_keep_alive(&request) && served < self.max_requests_per_connection;
+
+            let head_only = request.method == HTTPMethod::HEAD;
+            // HTTP/1.0 clients only know the end of the connection as delimiter
+            let chunked = request.version == "HTTP/1.1";
+
+            let response = self.find_response(request);
+
+            if response.length().is_none() && !chunked {
+                keep_alive = false;
+            }
 
-        let response = self.find_response(request);
+            let response = response.with_header(
+                Header::Connection,
+                if keep_alive { "keep-alive" } else { "close" },
+            );
 
-        stream.write_all(response.build().as_slice()).unwrap();
+            if Self::write_response(&stream, response, head_only, chunked).is_err() || !keep_alive {
+                break;
+            }
+        }
     }
 }
 
@@ -332,3 +916,301 @@ impl Default for App {
         App::new()
     }
 }
+
+/// Reads from a connection, timing out once `deadline` has passed however
+/// slowly the client trickles bytes in
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+
+        self.stream.set_read_timeout(Some(remaining))?;
+        (&mut &*self.stream).read(buf)
+    }
+}
+
+/// Errors that mean the listener itself is unusable
+fn is_fatal_accept_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported
+    )
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(raw: &[u8], max_body_size: usize) -> Result<Request, RequestError> {
+        App::read_request(&mut io::Cursor::new(raw), max_body_size).map(Option::unwrap)
+    }
+
+    #[test]
+    fn chunked_body() {
+        let request = read(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nX-Sum: 42\r\n\r\n",
+            64,
+        )
+        .unwrap();
+
+        assert_eq!(request.body, b"hello, world");
+        assert_eq!(request.header("X-Sum"), Some("42"));
+    }
+
+    #[test]
+    fn chunked_trailers_do_not_override() {
+        let request = read(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nX-A: head\r\n\r\n\
+              0\r\nx-a: trailer\r\n\r\n",
+            64,
+        )
+        .unwrap();
+
+        assert_eq!(request.header("X-A"), Some("head"));
+    }
+
+    #[test]
+    fn chunked_errors() {
+        let head = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let read = |chunks: &str| read(format!("{head}{chunks}").as_bytes(), 16);
+
+        for chunks in [
+            "zz\r\n",
+            "+5\r\nhello\r\n0\r\n\r\n",
+            "\r\n",
+            "3\r\nabcXY0\r\n\r\n",
+        ] {
+            assert!(
+                matches!(read(chunks), Err(RequestError::BadRequest(_))),
+                "{:?}",
+                chunks
+            );
+        }
+
+        for chunks in [
+            "ffffffffffffffff\r\n",
+            "1ffffffffffffffffffff\r\n",
+            "5\r\nhello\r\n9\r\n",
+            // Extensions and trailers count against the limit too
+            "1;ext=aaaaaaaaaaaa\r\n",
+            "1\r\na\r\n0\r\nX-Trailer: aaaa\r\n\r\n",
+        ] {
+            assert!(
+                matches!(read(chunks), Err(RequestError::TooLarge(_))),
+                "{:?}",
+                chunks
+            );
+        }
+
+        assert!(matches!(read("5\r\nhel"), Err(RequestError::Io(_))));
+    }
+
+    #[test]
+    fn refuses_ambiguous_framing() {
+        let both = b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let gzip = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n";
+
+        assert!(matches!(read(both, 64), Err(RequestError::BadRequest(_))));
+        assert!(matches!(read(gzip, 64), Err(RequestError::BadRequest(_))));
+    }
+
+    #[test]
+    fn bounded_request_head() {
+        let long_target = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LENGTH));
+        let long_header = format!(
+            "GET / HTTP/1.1\r\nX-A: {}\r\n\r\n",
+            "a".repeat(MAX_LINE_LENGTH)
+        );
+        let many_headers = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-A: a\r\n".repeat(MAX_HEAD_SIZE / 8)
+        );
+
+        assert!(matches!(
+            read(long_target.as_bytes(), 0),
+            Err(RequestError::UriTooLong(_))
+        ));
+        assert!(matches!(
+            read(long_header.as_bytes(), 0),
+            Err(RequestError::HeaderTooLarge(_))
+        ));
+        assert!(matches!(
+            read(many_headers.as_bytes(), 0),
+            Err(RequestError::HeaderTooLarge(_))
+        ));
+
+        let fits = format!("GET / HTTP/1.1\r\nX-A: {}\r\n\r\n", "a".repeat(1000));
+        assert!(read(fits.as_bytes(), 0).is_ok());
+    }
+
+    /// Answer `method target` the way a connection would
+    fn send(app: &App, method: &str, target: &str) -> Response {
+        let line = format!("{} {} HTTP/1.1\r\n\r\n", method, target);
+        app.handle(read(line.as_bytes(), 0).unwrap())
+    }
+
+    fn get(app: &App, target: &str) -> Response {
+        send(app, "GET", target)
+    }
+
+    /// Empty directory of its own for a test
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("saaba-{}-{}", name, std::process::id()));
+
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn directory_redirect_is_encoded() {
+        let dir = temp_dir("redirect");
+        fs::create_dir_all(dir.join("sub dir")).unwrap();
+        fs::create_dir_all(dir.join("caf\u{e9}")).unwrap();
+
+        let mut app = App::new();
+        app.static_("/static", dir.to_str().unwrap());
+
+        let location = |target| {
+            let response = get(&app, target);
+            find_header(&response.headers, Header::Location).map(str::to_owned)
+        };
+
+        assert_eq!(location("/static/sub%20dir").as_deref(), Some("sub%20dir/"));
+        assert_eq!(
+            location("/static/caf%C3%A9?a=1").as_deref(),
+            Some("caf%C3%A9/?a=1")
+        );
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn connect_reaches_routing() {
+        let connect = b"CONNECT example.com:443 HTTP/1.1\r\n\r\n";
+        let status = |app: &App| app.handle(read(connect, 0).unwrap()).status;
+
+        let mut app = App::new();
+        app.get("/", |_| "ok");
+        assert_eq!(status(&app), u32::from(ResponseCode::NotImplemented));
+
+        app.route("CONNECT", "example.com:443", |_| "tunnel");
+        assert_eq!(status(&app), u32::from(ResponseCode::OK));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn static_mounts_stay_in_their_directory() {
+        let dir = temp_dir("confined");
+        fs::create_dir_all(dir.join("root")).unwrap();
+        fs::write(dir.join("root/inner.txt"), "inner").unwrap();
+        fs::write(dir.join("outside.txt"), "outside").unwrap();
+        std::os::unix::fs::symlink(dir.join("outside.txt"), dir.join("root/out")).unwrap();
+
+        let mut app = App::new();
+        app.static_("/static", dir.join("root").to_str().unwrap());
+
+        let status = |target| get(&app, target).status;
+
+        assert_eq!(status("/static/inner.txt"), 200);
+        // Dot segments, encoded or not, are resolved before the mount is
+        assert_eq!(status("/static/../outside.txt"), 404);
+        assert_eq!(status("/static/%2e%2e/outside.txt"), 404);
+        assert_eq!(status("/static/out"), 403);
+        assert_eq!(status("/static/missing.txt"), 404);
+
+        let encoded_slash = read(b"GET /static/..%2foutside.txt HTTP/1.1\r\n\r\n", 0);
+        assert!(matches!(encoded_slash, Err(RequestError::BadRequest(_))));
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn method_not_allowed_lists_allowed_methods() {
+        let mut app = App::new();
+        app.get("/items", |_| "list").post("/items", |_| "created");
+        app.route_var(HTTPMethod::PUT, "/items/{id}", |_, _| "updated");
+
+        let response = send(&app, "DELETE", "/items");
+        assert_eq!(response.status, 405);
+        assert_eq!(
+            find_header(&response.headers, Header::Allow),
+            Some("GET, HEAD, POST, OPTIONS")
+        );
+
+        let response = send(&app, "GET", "/items/3");
+        assert_eq!(response.status, 405);
+        assert_eq!(
+            find_header(&response.headers, Header::Allow),
+            Some("PUT, OPTIONS")
+        );
+
+        assert_eq!(send(&app, "DELETE", "/nothing").status, 404);
+    }
+
+    #[test]
+    fn head_and_options_are_answered() {
+        let mut app = App::new();
+        app.get("/page", |_| "content");
+
+        // The body is kept here, the connection writer leaves it out
+        let head = send(&app, "HEAD", "/page");
+        assert_eq!(head.status, 200);
+        assert_eq!(head.length(), Some(7));
+
+        let options = send(&app, "OPTIONS", "/page");
+        assert_eq!(options.status, 204);
+        assert_eq!(
+            find_header(&options.headers, Header::Allow),
+            Some("GET, HEAD, OPTIONS")
+        );
+
+        assert_eq!(send(&app, "OPTIONS", "*").status, 204);
+        assert_eq!(send(&app, "OPTIONS", "/nothing").status, 404);
+
+        let mut written = Vec::new();
+        head.write(&mut written, true, true).unwrap();
+        assert!(String::from_utf8(written)
+            .unwrap()
+            .ends_with("Content-Length: 7\r\n\r\n"));
+    }
+
+    #[test]
+    fn mounted_routers() {
+        let mut users = Router::new();
+        users.get("/", |_| "users");
+        users.get_var("/{id:int}", |req: Request, _| req.path);
+
+        let mut admin = Router::new();
+        admin.get("/", |_| "admin");
+
+        let mut app = App::new();
+        app.mount("/users", users).mount("/users/admin", admin);
+
+        let body = |target| get(&app, target).body.into_bytes().unwrap();
+
+        assert_eq!(body("/users"), b"users");
+        assert_eq!(body("/users/"), b"users");
+        // Handlers see the path under their mount
+        assert_eq!(body("/users/12"), b"/12");
+        // The longest prefix wins
+        assert_eq!(body("/users/admin"), b"admin");
+        assert_eq!(get(&app, "/usersx").status, 404);
+    }
+}
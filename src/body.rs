@@ -0,0 +1,154 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::constants::CRLF;
+
+/// Size of the chunks a stream is written in
+const CHUNK_SIZE: usize = 8192;
+
+/// Response body, written to the connection as it is read
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// The next `length` bytes of a file
+    File {
+        file: File,
+        length: u64,
+    },
+    /// Any reader, sent with chunked encoding unless a `Content-Length` is set
+    Stream(Box<dyn Read + Send>),
+}
+
+impl Body {
+    pub fn empty() -> Self {
+        Body::Bytes(Vec::new())
+    }
+
+    pub fn stream(reader: impl Read + Send + 'static) -> Self {
+        Body::Stream(Box::new(reader))
+    }
+
+    /// Length in bytes, `None` for a stream
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::File { length, .. } => Some(*length),
+            Body::Stream(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// `length` bytes starting at `start`
+    pub fn slice(self, start: u64, length: u64) -> Result<Body, io::Error> {
+        match self {
+            Body::Bytes(bytes) => {
+                let end = bytes.len().min(start.saturating_add(length) as usize);
+                let start = bytes.len().min(start as usize);
+
+                Ok(Body::Bytes(bytes[start..end].to_vec()))
+            }
+            Body::File {
+                mut file,
+                length: total,
+            } => {
+                file.seek(SeekFrom::Current(start as i64))?;
+
+                Ok(Body::File {
+                    file,
+                    length: length.min(total.saturating_sub(start)),
+                })
+            }
+            Body::Stream(mut reader) => {
+                io::copy(&mut (&mut reader).take(start), &mut io::sink())?;
+
+                Ok(Body::stream(reader.take(length)))
+            }
+        }
+    }
+
+    /// Read the whole body into memory
+    pub fn into_bytes(self) -> Result<Vec<u8>, io::Error> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Write the body as it is
+    pub fn write_to(self, writer: &mut impl Write) -> Result<(), io::Error> {
+        match self {
+            Body::Bytes(bytes) => writer.write_all(&bytes),
+            Body::File { file, length } => {
+                let copied = io::copy(&mut file.take(length), writer)?;
+
+                // The file shrank since its length was taken
+                match copied == length {
+                    true => Ok(()),
+                    false => Err(io::ErrorKind::UnexpectedEof.into()),
+                }
+            }
+            Body::Stream(mut reader) => io::copy(&mut reader, writer).map(|_| ()),
+        }
+    }
+
+    /// Write the body with chunked transfer coding
+    pub fn write_chunked(self, writer: &mut impl Write) -> Result<(), io::Error> {
+        let mut reader: Box<dyn Read> = match self {
+            Body::Bytes(bytes) => Box::new(io::Cursor::new(bytes)),
+            Body::File { file, length } => Box::new(file.take(length)),
+            Body::Stream(reader) => reader,
+        };
+
+        let mut buf = vec![0; CHUNK_SIZE];
+
+        loop {
+            let read = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            write!(writer, "{:x}{CRLF}", read)?;
+            writer.write_all(&buf[..read])?;
+            writer.write_all(CRLF.as_bytes())?;
+        }
+
+        write!(writer, "0{CRLF}{CRLF}")
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body::empty()
+    }
+}
+
+impl std::fmt::Debug for Body {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Body::File { length, .. } => f.debug_struct("File").field("length", length).finish(),
+            Body::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(value: Vec<u8>) -> Self {
+        Body::Bytes(value)
+    }
+}
+
+impl From<String> for Body {
+    fn from(value: String) -> Self {
+        Body::Bytes(value.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(value: &str) -> Self {
+        Body::from(value.to_string())
+    }
+}
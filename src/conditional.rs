@@ -0,0 +1,262 @@
+//! Conditional and range requests, answered from the validators a response
+//! carries (`ETag`, `Last-Modified`) and its `Accept-Ranges: bytes`, as set
+//! by `Response::file`. Preconditions are evaluated in the order of
+//! RFC 9110, section 13.2.2.
+
+use std::time::SystemTime;
+
+use crate::header::Header;
+use crate::utils::{find_header, parse_http_date, remove_header};
+use crate::{Body, HTTPMethod, Request, Response, ResponseCode};
+
+/// Conditional and range headers of a request
+pub struct Conditions {
+    if_match: Option<String>,
+    if_none_match: Option<String>,
+    if_modified_since: Option<SystemTime>,
+    if_unmodified_since: Option<SystemTime>,
+    if_range: Option<String>,
+    range: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Satisfiable(u64, u64),
+    Unsatisfiable,
+}
+
+impl Conditions {
+    /// `None` for methods conditions do not apply to
+    pub fn from_request(request: &Request) -> Option<Self> {
+        if !matches!(request.method, HTTPMethod::GET | HTTPMethod::HEAD) {
+            return None;
+        }
+
+        let header = |name| request.header(name).map(str::to_owned);
+        let date = |name| request.header(name).and_then(parse_http_date);
+
+        Some(Conditions {
+            if_match: header(Header::IfMatch),
+            if_none_match: header(Header::IfNoneMatch),
+            if_modified_since: date(Header::IfModifiedSince),
+            if_unmodified_since: date(Header::IfUnmodifiedSince),
+            if_range: header(Header::IfRange),
+            range: header(Header::Range),
+        })
+    }
+
+    pub fn apply(&self, response: Response) -> Response {
+        let ok = response.status == u32::from(ResponseCode::OK);
+        let etag = find_header(&response.headers, Header::ETag).map(str::to_owned);
+        let last_modified =
+            find_header(&response.headers, Header::LastModified).and_then(parse_http_date);
+
+        if !ok || (etag.is_none() && last_modified.is_none()) {
+            return response;
+        }
+
+        let etag = etag.as_deref();
+
+        if let Some(if_match) = &self.if_match {
+            if !etag_matches(if_match, etag, true) {
+                return Response::error(ResponseCode::PreconditionFailed);
+            }
+        } else if let (Some(since), Some(modified)) = (self.if_unmodified_since, last_modified) {
+            if modified > since {
+                return Response::error(ResponseCode::PreconditionFailed);
+            }
+        }
+
+        if let Some(if_none_match) = &self.if_none_match {
+            if etag_matches(if_none_match, etag, false) {
+                return not_modified(response);
+            }
+        } else if let (Some(since), Some(modified)) = (self.if_modified_since, last_modified) {
+            if modified <= since {
+                return not_modified(response);
+            }
+        }
+
+        self.apply_range(response, etag, last_modified)
+    }
+
+    fn apply_range(
+        &self,
+        response: Response,
+        etag: Option<&str>,
+        last_modified: Option<SystemTime>,
+    ) -> Response {
+        let accepts_ranges = find_header(&response.headers, Header::AcceptRanges) == Some("bytes");
+
+        let Some(range) = self.range.as_deref().filter(|_| accepts_ranges) else {
+            return response;
+        };
+
+        // A range of a representation that changed since is useless, send it whole
+        if let Some(if_range) = &self.if_range {
+            let unchanged = match if_range.starts_with('"') {
+                true => etag.is_some_and(|etag| etag == if_range),
+                false => last_modified.is_some() && parse_http_date(if_range) == last_modified,
+            };
+
+            if !unchanged {
+                return response;
+            }
+        }
+
+        let Some(length) = response.body.len() else {
+            return response;
+        };
+
+        match parse_range(range, length) {
+            None => response,
+            Some(ByteRange::Unsatisfiable) => Response::error(ResponseCode::RangeNotSatisfiable)
+                .with_header(Header::ContentRange, format!("bytes */{}", length)),
+            Some(ByteRange::Satisfiable(start, end)) => {
+                let mut response = response
+                    .with_status(ResponseCode::PartialContent.into())
+                    .with_header(
+                        Header::ContentRange,
+                        format!("bytes {}-{}/{}", start, end, length),
+                    );
+
+                match std::mem::take(&mut response.body).slice(start, end - start + 1) {
+                    Ok(body) => response.with_body(body),
+                    Err(e) => {
+                        log::error!("Cannot read the requested range: {}", e);
+                        Response::error(ResponseCode::InternalServerError)
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether an `If-Match` or `If-None-Match` list matches the current ETag
+fn etag_matches(list: &str, etag: Option<&str>, strong: bool) -> bool {
+    if list.trim() == "*" {
+        return true;
+    }
+
+    let Some(etag) = etag else {
+        return false;
+    };
+
+    // Strong comparison fails for weak tags, weak comparison ignores the `W/`
+    let opaque = |tag: &str| match (tag.strip_prefix("W/"), strong) {
+        (Some(_), true) => None,
+        (Some(weak), false) => Some(weak.to_string()),
+        (None, _) => Some(tag.to_string()),
+    };
+
+    let Some(current) = opaque(etag) else {
+        return false;
+    };
+
+    list.split(',')
+        .filter_map(|tag| opaque(tag.trim()))
+        .any(|tag| tag == current)
+}
+
+/// Keep the validators, drop the body
+fn not_modified(mut response: Response) -> Response {
+    remove_header(&mut response.headers, Header::ContentLength);
+    remove_header(&mut response.headers, Header::ContentType);
+
+    response.body = Body::empty();
+    response.with_status(ResponseCode::NotModified.into())
+}
+
+/// A single `bytes=` range. `None` if the header is to be ignored: other
+/// units, several ranges or invalid syntax
+fn parse_range(range: &str, length: u64) -> Option<ByteRange> {
+    let (unit, spec) = range.trim().split_once('=')?;
+
+    if !unit.eq_ignore_ascii_case("bytes") || spec.contains(',') {
+        return None;
+    }
+
+    let number = |n: &str| match !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) {
+        true => n.parse::<u64>().ok().or(Some(u64::MAX)),
+        false => None,
+    };
+
+    let (first, last) = spec.trim().split_once('-')?;
+
+    let (start, end) = match (first, last) {
+        // Suffix: the last `n` bytes
+        ("", n) => match number(n)? {
+            0 => return Some(ByteRange::Unsatisfiable),
+            n => (length.saturating_sub(n), length.saturating_sub(1)),
+        },
+        (start, "") => (number(start)?, length.saturating_sub(1)),
+        (start, end) => {
+            let (start, end) = (number(start)?, number(end)?);
+
+            if start > end {
+                return None;
+            }
+
+            (start, end.min(length.saturating_sub(1)))
+        }
+    };
+
+    match start < length {
+        true => Some(ByteRange::Satisfiable(start, end)),
+        false => Some(ByteRange::Unsatisfiable),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_ranges() {
+        use ByteRange::*;
+
+        let cases = [
+            ("bytes=0-4", Some(Satisfiable(0, 4))),
+            ("bytes=5-", Some(Satisfiable(5, 9))),
+            ("bytes=-3", Some(Satisfiable(7, 9))),
+            ("bytes=-20", Some(Satisfiable(0, 9))),
+            ("bytes=8-100", Some(Satisfiable(8, 9))),
+            ("BYTES=1-1", Some(Satisfiable(1, 1))),
+            ("bytes=10-", Some(Unsatisfiable)),
+            ("bytes=-0", Some(Unsatisfiable)),
+            ("bytes=99999999999999999999-", Some(Unsatisfiable)),
+            ("items=0-4", None),
+            ("bytes=0-1,3-4", None),
+            ("bytes=5-1", None),
+            ("bytes=a-b", None),
+            ("bytes=+1-2", None),
+            ("bytes=-", None),
+            ("bytes", None),
+        ];
+
+        for (range, expected) in cases {
+            assert_eq!(parse_range(range, 10), expected, "{}", range);
+        }
+
+        assert_eq!(parse_range("bytes=0-", 0), Some(Unsatisfiable));
+        assert_eq!(parse_range("bytes=-5", 0), Some(Unsatisfiable));
+    }
+
+    #[test]
+    fn etag_comparison() {
+        let strong = Some("\"a\"");
+        let weak = Some("W/\"a\"");
+
+        assert!(etag_matches("*", None, true));
+        assert!(etag_matches("\"a\"", strong, true));
+        assert!(etag_matches("\"b\", \"a\"", strong, true));
+        assert!(!etag_matches("\"b\"", strong, true));
+        assert!(!etag_matches("\"a\"", None, false));
+
+        // Weak tags only ever match with weak comparison
+        assert!(etag_matches("W/\"a\"", strong, false));
+        assert!(etag_matches("\"a\"", weak, false));
+        assert!(!etag_matches("W/\"a\"", strong, true));
+        assert!(!etag_matches("\"a\"", weak, true));
+    }
+}
@@ -1,6 +1,19 @@
 #[allow(non_snake_case, non_upper_case_globals)]
 pub mod Header {
+    pub const AcceptRanges: &str = "Accept-Ranges";
+    pub const Allow: &str = "Allow";
+    pub const Connection: &str = "Connection";
     pub const ContentType: &str = "Content-Type";
     pub const ContentLength: &str = "Content-Length";
+    pub const ContentRange: &str = "Content-Range";
+    pub const ETag: &str = "ETag";
+    pub const IfMatch: &str = "If-Match";
+    pub const IfModifiedSince: &str = "If-Modified-Since";
+    pub const IfNoneMatch: &str = "If-None-Match";
+    pub const IfRange: &str = "If-Range";
+    pub const IfUnmodifiedSince: &str = "If-Unmodified-Since";
+    pub const LastModified: &str = "Last-Modified";
     pub const Location: &str = "Location";
+    pub const Range: &str = "Range";
+    pub const TransferEncoding: &str = "Transfer-Encoding";
 }
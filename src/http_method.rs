@@ -1,14 +1,18 @@
-#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug, Default)]
+/// Request methods from RFC 9110, plus PATCH (RFC 5789).
+/// Anything else that is a valid token is kept as `Extension`
+#[derive(Eq, Hash, PartialEq, Ord, PartialOrd, Clone, Debug, Default)]
 pub enum HTTPMethod {
     #[default]
     GET,
+    HEAD,
     POST,
     PUT,
     DELETE,
-    UPDATE,
-    CONFIG,
-    HEAD,
+    CONNECT,
+    OPTIONS,
     TRACE,
+    PATCH,
+    Extension(String),
 }
 
 impl TryFrom<String> for HTTPMethod {
@@ -19,21 +23,23 @@ impl TryFrom<String> for HTTPMethod {
     }
 }
 
-
 impl TryFrom<&str> for HTTPMethod {
     type Error = UnknownMethodError;
 
+    /// Method names are case-sensitive: `get` is an extension method, not GET
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value.to_uppercase().as_str() {
+        match value {
             "GET" => Ok(Self::GET),
+            "HEAD" => Ok(Self::HEAD),
             "POST" => Ok(Self::POST),
             "PUT" => Ok(Self::PUT),
             "DELETE" => Ok(Self::DELETE),
-            "UPDATE" => Ok(Self::UPDATE),
-            "CONFIG" => Ok(Self::CONFIG),
-            "HEAD" => Ok(Self::HEAD),
+            "CONNECT" => Ok(Self::CONNECT),
+            "OPTIONS" => Ok(Self::OPTIONS),
             "TRACE" => Ok(Self::TRACE),
+            "PATCH" => Ok(Self::PATCH),
 
+            m if is_token(m) => Ok(Self::Extension(m.to_string())),
             m => Err(UnknownMethodError {
                 method: m.to_string(),
             }),
@@ -41,9 +47,20 @@ impl TryFrom<&str> for HTTPMethod {
     }
 }
 
+/// `token` from RFC 9110, section 5.6.2
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
 impl std::fmt::Display for HTTPMethod {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            HTTPMethod::Extension(method) => write!(f, "{}", method),
+            method => write!(f, "{:?}", method),
+        }
     }
 }
 
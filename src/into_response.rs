@@ -0,0 +1,89 @@
+use crate::{Response, ResponseCode};
+
+/// Values a handler can answer with. Implement it for your own types to
+/// return them from handlers directly
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        Response::from(self)
+    }
+}
+
+impl IntoResponse for &str {
+    fn into_response(self) -> Response {
+        Response::from(self)
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Response {
+        Response::from(self)
+    }
+}
+
+impl IntoResponse for &[u8] {
+    fn into_response(self) -> Response {
+        Response::from(self.to_vec())
+    }
+}
+
+/// An empty response with that status
+impl IntoResponse for ResponseCode {
+    fn into_response(self) -> Response {
+        Response::from_status(self)
+    }
+}
+
+/// `204 No Content`
+impl IntoResponse for () {
+    fn into_response(self) -> Response {
+        Response::from_status(ResponseCode::NoContent)
+    }
+}
+
+/// `None` is `404 Not Found`
+impl<T: IntoResponse> IntoResponse for Option<T> {
+    fn into_response(self) -> Response {
+        match self {
+            Some(value) => value.into_response(),
+            None => Response::not_found(),
+        }
+    }
+}
+
+/// Body with a status
+impl<T: IntoResponse> IntoResponse for (ResponseCode, T) {
+    fn into_response(self) -> Response {
+        let (code, body) = self;
+        body.into_response().with_status(code.into())
+    }
+}
+
+/// Body with a status and headers, e.g. `(code, [("Cache-Control", "no-cache")], body)`
+impl<T, H, K, V> IntoResponse for (ResponseCode, H, T)
+where
+    T: IntoResponse,
+    H: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<String>,
+{
+    fn into_response(self) -> Response {
+        let (code, headers, body) = self;
+        let mut response = body.into_response().with_status(code.into());
+
+        for (key, value) in headers {
+            response.set_header(key, value);
+        }
+
+        response
+    }
+}
@@ -1,15 +1,33 @@
 mod app;
+mod body;
+mod conditional;
 mod constants;
+mod extensions;
 mod http_method;
+mod into_response;
+mod middleware;
 mod mime;
+mod pool;
 mod request;
 mod response;
 mod utils;
 mod header;
 mod response_code;
+mod response_error;
+mod route_tree;
+mod router;
+mod static_files;
 
 pub use app::App;
+pub use body::Body;
+pub use extensions::Extensions;
 pub use http_method::HTTPMethod;
-pub use request::Request;
+pub use into_response::IntoResponse;
+pub use middleware::Next;
+pub use pool::Backpressure;
+pub use request::{ParamError, Request};
 pub use response::Response;
 pub use response_code::ResponseCode;
+pub use response_error::{HandlerResult, ResponseError};
+pub use router::Router;
+pub use static_files::StaticOptions;
@@ -0,0 +1,29 @@
+use crate::{Request, Response};
+
+pub type Middleware = Box<dyn Fn(Request, Next) -> Response + Send + Sync + 'static>;
+
+/// The rest of the middleware chain, ending with the handler
+pub struct Next<'a> {
+    middlewares: &'a [&'a Middleware],
+    endpoint: &'a dyn Fn(Request) -> Response,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middlewares: &'a [&'a Middleware],
+        endpoint: &'a dyn Fn(Request) -> Response,
+    ) -> Self {
+        Next {
+            middlewares,
+            endpoint,
+        }
+    }
+
+    /// Pass the request on and get the response back
+    pub fn run(self, request: Request) -> Response {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => middleware(request, Next::new(rest, self.endpoint)),
+            None => (self.endpoint)(request),
+        }
+    }
+}
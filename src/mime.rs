@@ -1,114 +1,450 @@
 // We can
 // 1. Guess by extension
-// 2. Read file header
+// 2. Read file header, if the extension is missing or unknown
 
-fn ext_to_mime(ext: &str) -> &str {
-    match ext {
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Bytes read from a file to sniff its type
+const SNIFF_LENGTH: usize = 512;
+
+/// Keep in sync with `tools/mime_types.txt`
+fn ext_to_mime(ext: &str) -> Option<&'static str> {
+    match ext.to_ascii_lowercase().as_str() {
         // application
-        "7z" => "application/x-7z-compressed",
-        "abw" => "application/x-abiword",
-        "arc" => "application/x-freearc",
-        "azw" => "application/vnd.amazon.ebook",
-        "bin" => "application/octet-stream",
-        "bz" => "application/x-bzip",
-        "bz2" => "application/x-bzip2",
-        "cda" => "application/x-cdf",
-        "csh" => "application/x-csh",
-        "doc" => "application/msword",
-        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
-        "eot" => "application/vnd.ms-fontobject",
-        "epub" => "application/epub+zip",
-        "gz" => "application/gzip",
-        "jar" => "application/java-archive",
-        "json" => "application/json",
-        "jsonld" => "application/ld+json",
-        "mpkg" => "application/vnd.apple.installer+xml",
-        "odp" => "application/vnd.oasis.opendocument.presentation",
-        "ods" => "application/vnd.oasis.opendocument.spreadsheet",
-        "odt" => "application/vnd.oasis.opendocument.text",
-        "ogx" => "application/ogg",
-        "pdf" => "application/pdf",
-        "php" => "application/x-httpd-php",
-        "ppt" => "application/vnd.ms-powerpoint",
-        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
-        "rar" => "application/vnd.rar",
-        "rtf" => "application/rtf",
-        "sh" => "application/x-sh",
-        "tar" => "application/x-tar",
-        "vsd" => "application/vnd.visio",
-        "xhtml" => "application/xhtml+xml",
-        "xls" => "application/vnd.ms-excel",
-        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
-        "xml" => "application/xml",
-        "xul" => "application/vnd.mozilla.xul+xml",
-        "zip" => "application/zip",
+        "atom" => Some("application/atom+xml"),
+        "epub" => Some("application/epub+zip"),
+        "gz" => Some("application/gzip"),
+        "jar" => Some("application/java-archive"),
+        "json" => Some("application/json"),
+        "map" => Some("application/json"),
+        "jsonld" => Some("application/ld+json"),
+        "webmanifest" => Some("application/manifest+json"),
+        "doc" => Some("application/msword"),
+        "bin" => Some("application/octet-stream"),
+        "ogx" => Some("application/ogg"),
+        "pdf" => Some("application/pdf"),
+        "rss" => Some("application/rss+xml"),
+        "rtf" => Some("application/rtf"),
+        "toml" => Some("application/toml"),
+        "azw" => Some("application/vnd.amazon.ebook"),
+        "mpkg" => Some("application/vnd.apple.installer+xml"),
+        "xul" => Some("application/vnd.mozilla.xul+xml"),
+        "xls" => Some("application/vnd.ms-excel"),
+        "eot" => Some("application/vnd.ms-fontobject"),
+        "ppt" => Some("application/vnd.ms-powerpoint"),
+        "odp" => Some("application/vnd.oasis.opendocument.presentation"),
+        "ods" => Some("application/vnd.oasis.opendocument.spreadsheet"),
+        "odt" => Some("application/vnd.oasis.opendocument.text"),
+        "pptx" => Some("application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+        "xlsx" => Some("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+        "docx" => Some("application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+        "rar" => Some("application/vnd.rar"),
+        "vsd" => Some("application/vnd.visio"),
+        "wasm" => Some("application/wasm"),
+        "7z" => Some("application/x-7z-compressed"),
+        "abw" => Some("application/x-abiword"),
+        "bz" => Some("application/x-bzip"),
+        "bz2" => Some("application/x-bzip2"),
+        "cda" => Some("application/x-cdf"),
+        "csh" => Some("application/x-csh"),
+        "arc" => Some("application/x-freearc"),
+        "php" => Some("application/x-httpd-php"),
+        "sh" => Some("application/x-sh"),
+        "tar" => Some("application/x-tar"),
+        "xz" => Some("application/x-xz"),
+        "xhtml" => Some("application/xhtml+xml"),
+        "xml" => Some("application/xml"),
+        "yaml" => Some("application/yaml"),
+        "yml" => Some("application/yaml"),
+        "zip" => Some("application/zip"),
+        "zst" => Some("application/zstd"),
 
         // audio
-        "aac" => "audio/aac",
-        "mid" => "audio/midi",
-        "midi" => "audio/midi",
-        "mp3" => "audio/mpeg",
-        "oga" => "audio/ogg",
-        "opus" => "audio/ogg",
-        "wav" => "audio/wav",
-        "weba" => "audio/webm",
+        "aac" => Some("audio/aac"),
+        "flac" => Some("audio/flac"),
+        "mid" => Some("audio/midi"),
+        "midi" => Some("audio/midi"),
+        "m4a" => Some("audio/mp4"),
+        "mp3" => Some("audio/mpeg"),
+        "oga" => Some("audio/ogg"),
+        "ogg" => Some("audio/ogg"),
+        "opus" => Some("audio/ogg"),
+        "wav" => Some("audio/wav"),
+        "weba" => Some("audio/webm"),
 
         // font
-        "otf" => "font/otf",
-        "ttf" => "font/ttf",
-        "woff" => "font/woff",
-        "woff2" => "font/woff2",
+        "ttc" => Some("font/collection"),
+        "otf" => Some("font/otf"),
+        "ttf" => Some("font/ttf"),
+        "woff" => Some("font/woff"),
+        "woff2" => Some("font/woff2"),
 
         // image
-        "apng" => "image/apng",
-        "avif" => "image/avif",
-        "bmp" => "image/bmp",
-        "gif" => "image/gif",
-        "jpeg" => "image/jpeg",
-        "jpg" => "image/jpeg",
-        "png" => "image/png",
-        "svg" => "image/svg+xml",
-        "ico" => "image/vnd.microsoft.icon",
-        "webp" => "image/webp",
-        "tif" => "image/tiff",
-        "tiff" => "image/tiff",
+        "apng" => Some("image/apng"),
+        "avif" => Some("image/avif"),
+        "bmp" => Some("image/bmp"),
+        "gif" => Some("image/gif"),
+        "heic" => Some("image/heic"),
+        "heif" => Some("image/heif"),
+        "jpeg" => Some("image/jpeg"),
+        "jpg" => Some("image/jpeg"),
+        "jxl" => Some("image/jxl"),
+        "png" => Some("image/png"),
+        "svg" => Some("image/svg+xml"),
+        "tif" => Some("image/tiff"),
+        "tiff" => Some("image/tiff"),
+        "ico" => Some("image/vnd.microsoft.icon"),
+        "webp" => Some("image/webp"),
 
         // text
-        "ics" => "text/calendar",
-        "css" => "text/css",
-        "csv" => "text/csv",
-        "htm" => "text/html",
-        "html" => "text/html",
-        "js" => "text/javascript",
-        "mjs" => "text/javascript",
-        "txt" => "text/plain",
+        "ics" => Some("text/calendar"),
+        "css" => Some("text/css"),
+        "csv" => Some("text/csv"),
+        "htm" => Some("text/html"),
+        "html" => Some("text/html"),
+        "cjs" => Some("text/javascript"),
+        "js" => Some("text/javascript"),
+        "mjs" => Some("text/javascript"),
+        "md" => Some("text/markdown"),
+        "txt" => Some("text/plain"),
+        "tsv" => Some("text/tab-separated-values"),
+        "vtt" => Some("text/vtt"),
 
         // video
-        "3gp" => "video/3gpp",
-        "3g2" => "video/3gpp2",
-        "mp4" => "video/mp4",
-        "mpeg" => "video/mpeg",
-        "ogv" => "video/ogg",
-        "webm" => "video/webm",
-        "avi" => "video/x-msvideo",
-
+        "3gp" => Some("video/3gpp"),
+        "3g2" => Some("video/3gpp2"),
+        "ts" => Some("video/mp2t"),
+        "m4v" => Some("video/mp4"),
+        "mp4" => Some("video/mp4"),
+        "mpeg" => Some("video/mpeg"),
+        "mpg" => Some("video/mpeg"),
+        "ogv" => Some("video/ogg"),
+        "mov" => Some("video/quicktime"),
+        "webm" => Some("video/webm"),
+        "mkv" => Some("video/x-matroska"),
+        "avi" => Some("video/x-msvideo"),
         // _
-        _ => "",
+        _ => None,
     }
 }
 
+/// `Content-Type` value for a MIME type, text types being served as UTF-8
+pub fn with_charset(mime: &str) -> String {
+    match mime.starts_with("text/") && !mime.contains(';') {
+        true => format!("{}; charset=utf-8", mime),
+        false => mime.to_string(),
+    }
+}
+
+/// `Content-Type` for a file name, from its extension (case-insensitive)
 pub fn guess_mime(filename: &str) -> Option<String> {
-    let parts = filename.split(".").collect::<Vec<&str>>();
-    let ext = parts.last();
+    let ext = Path::new(filename).extension()?.to_str()?;
+
+    ext_to_mime(ext).map(with_charset)
+}
+
+/// Signatures at the start of a file
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"PK\x05\x06", "application/zip"),
+    (b"\x1f\x8b\x08", "application/gzip"),
+    (b"\0asm", "application/wasm"),
+];
 
-    if let Some(ext) = ext {
-        let mime = ext_to_mime(ext);
+/// Tags an HTML document may start with, matched case-insensitively
+const HTML_TAGS: &[&[u8]] = &[
+    b"<!doctype html",
+    b"<html",
+    b"<head",
+    b"<body",
+    b"<script",
+    b"<iframe",
+    b"<style",
+    b"<title",
+    b"<table",
+    b"<div",
+    b"<font",
+    b"<h1",
+    b"<br",
+    b"<a",
+    b"<b",
+    b"<p",
+    b"<!--",
+];
 
-        return match mime {
-            "" => None,
-            mime_type => Some(mime_type.to_string()),
-        };
+fn is_html(bytes: &[u8]) -> bool {
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    let bytes = &bytes[start..];
+
+    HTML_TAGS.iter().any(|tag| {
+        bytes.len() > tag.len()
+            && bytes[..tag.len()].eq_ignore_ascii_case(tag)
+            // The tag name must end there: `<a>` or `<a href>`, not `<abbr>`
+            && matches!(bytes[tag.len()], b' ' | b'>' | b'\t' | b'\n' | b'\r' | b'\x0c')
+    })
+}
+
+/// UTF-8 without binary control characters. A sequence cut by the end of
+/// the sample does not count against it
+fn is_text(bytes: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return false,
+    };
+
+    !valid.is_empty()
+        && valid.bytes().all(|b| {
+            !b.is_ascii_control() || matches!(b, b'\t' | b'\n' | b'\r' | b'\x0c' | b'\x1b')
+        })
+}
+
+/// `Content-Type` from the first bytes of a file
+pub fn sniff_mime(bytes: &[u8]) -> Option<String> {
+    let bytes = &bytes[..bytes.len().min(SNIFF_LENGTH)];
+
+    if let Some((_, mime)) = MAGIC.iter().find(|(magic, _)| bytes.starts_with(magic)) {
+        return Some(mime.to_string());
+    }
+
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp".to_string());
+    }
+
+    let text = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+
+    if is_html(text) {
+        return Some(with_charset("text/html"));
+    }
+
+    if is_text(text) {
+        return Some(with_charset("text/plain"));
     }
 
     None
 }
+
+/// Extension to MIME type mappings registered on top of the built-in table.
+/// Extensions may span several dots, e.g. `mjs.map`
+#[derive(Clone, Debug, Default)]
+pub struct MimeTypes {
+    types: HashMap<String, String>,
+}
+
+impl MimeTypes {
+    pub fn new() -> Self {
+        MimeTypes {
+            types: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, ext: &str, mime: &str) {
+        let ext = ext.trim_start_matches('.').to_ascii_lowercase();
+        self.types.insert(ext, mime.to_string());
+    }
+
+    /// Add mappings in the `/etc/mime.types` format: a MIME type followed by
+    /// its extensions on each line, `#` starting a comment
+    pub fn parse(&mut self, source: &str) {
+        for line in source.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+
+            let Some(mime) = fields.next() else {
+                continue;
+            };
+
+            for ext in fields {
+                self.insert(ext, mime);
+            }
+        }
+    }
+
+    /// `Content-Type` for a file name, trying the longest extension first
+    pub fn lookup(&self, filename: &str) -> Option<String> {
+        if self.types.is_empty() {
+            return None;
+        }
+
+        let name = Path::new(filename)
+            .file_name()?
+            .to_str()?
+            .to_ascii_lowercase();
+
+        name.match_indices('.')
+            .find_map(|(i, _)| self.types.get(&name[i + 1..]))
+            .map(|mime| with_charset(mime))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ext_to_mime_ignores_case() {
+        assert_eq!(ext_to_mime("png"), Some("image/png"));
+        assert_eq!(ext_to_mime("PNG"), Some("image/png"));
+        assert_eq!(ext_to_mime("Html"), Some("text/html"));
+        assert_eq!(ext_to_mime("unknown"), None);
+        assert_eq!(ext_to_mime(""), None);
+    }
+
+    #[test]
+    fn with_charset_only_for_text() {
+        assert_eq!(with_charset("text/css"), "text/css; charset=utf-8");
+        assert_eq!(
+            with_charset("text/html; charset=latin1"),
+            "text/html; charset=latin1"
+        );
+        assert_eq!(with_charset("application/json"), "application/json");
+        assert_eq!(with_charset("image/svg+xml"), "image/svg+xml");
+    }
+
+    #[test]
+    fn guess_mime_from_extension() {
+        assert_eq!(
+            guess_mime("index.html").as_deref(),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(
+            guess_mime("/static/APP.JS").as_deref(),
+            Some("text/javascript; charset=utf-8")
+        );
+        assert_eq!(guess_mime("photo.JPEG").as_deref(), Some("image/jpeg"));
+        assert_eq!(
+            guess_mime("archive.tar.gz").as_deref(),
+            Some("application/gzip")
+        );
+        assert_eq!(guess_mime("Makefile"), None);
+        assert_eq!(guess_mime(".gitignore"), None);
+        assert_eq!(guess_mime("file.nope"), None);
+    }
+
+    #[test]
+    fn sniff_magic_bytes() {
+        assert_eq!(
+            sniff_mime(b"\x89PNG\r\n\x1a\n\0\0").as_deref(),
+            Some("image/png")
+        );
+        assert_eq!(
+            sniff_mime(b"\xff\xd8\xff\xe0").as_deref(),
+            Some("image/jpeg")
+        );
+        assert_eq!(sniff_mime(b"GIF89a...").as_deref(), Some("image/gif"));
+        assert_eq!(sniff_mime(b"%PDF-1.7").as_deref(), Some("application/pdf"));
+        assert_eq!(
+            sniff_mime(b"PK\x03\x04").as_deref(),
+            Some("application/zip")
+        );
+        assert_eq!(
+            sniff_mime(b"RIFF\0\0\0\0WEBPVP8 ").as_deref(),
+            Some("image/webp")
+        );
+        assert_eq!(sniff_mime(b"RIFF\0\0\0\0WAVE"), None);
+    }
+
+    #[test]
+    fn sniff_html() {
+        let html = Some("text/html; charset=utf-8");
+
+        assert_eq!(sniff_mime(b"<!DOCTYPE html>\n<html>").as_deref(), html);
+        assert_eq!(sniff_mime(b"\n  <p>hello</p>").as_deref(), html);
+        assert_eq!(sniff_mime(b"\xef\xbb\xbf<html lang=en>").as_deref(), html);
+        // The tag name has to end after the prefix
+        assert_ne!(sniff_mime(b"<abbr>x</abbr>").as_deref(), html);
+        assert_ne!(sniff_mime(b"<a").as_deref(), html);
+    }
+
+    #[test]
+    fn sniff_text() {
+        let text = Some("text/plain; charset=utf-8");
+
+        assert_eq!(sniff_mime(b"hello\tworld\r\n").as_deref(), text);
+        assert_eq!(sniff_mime("h\u{e9}llo".as_bytes()).as_deref(), text);
+        // A multi-byte character cut by the end of the sample
+        assert_eq!(sniff_mime(&"\u{e9}".as_bytes()[..1]), None);
+        assert_eq!(sniff_mime(&"a\u{e9}".as_bytes()[..2]).as_deref(), text);
+
+        assert_eq!(sniff_mime(b""), None);
+        assert_eq!(sniff_mime(b"\0\x01\x02binary"), None);
+        assert_eq!(sniff_mime(b"\xff\xfe\xfd"), None);
+    }
+
+    #[test]
+    fn sniff_only_reads_the_start() {
+        let mut bytes = vec![b'a'; SNIFF_LENGTH];
+        bytes.push(0);
+
+        assert_eq!(
+            sniff_mime(&bytes).as_deref(),
+            Some("text/plain; charset=utf-8")
+        );
+    }
+
+    #[test]
+    fn mime_types_lookup() {
+        let mut types = MimeTypes::new();
+        assert_eq!(types.lookup("a.txt"), None);
+
+        types.insert(".MD", "text/markdown");
+        types.insert("map", "application/json");
+        types.insert("mjs.map", "application/source-map");
+
+        assert_eq!(
+            types.lookup("README.md").as_deref(),
+            Some("text/markdown; charset=utf-8")
+        );
+        assert_eq!(
+            types.lookup("/srv/x/readme.MD").as_deref(),
+            Some("text/markdown; charset=utf-8")
+        );
+        assert_eq!(
+            types.lookup("app.js.map").as_deref(),
+            Some("application/json")
+        );
+        // The longest registered extension wins
+        assert_eq!(
+            types.lookup("app.mjs.map").as_deref(),
+            Some("application/source-map")
+        );
+        assert_eq!(types.lookup("md"), None);
+        assert_eq!(types.lookup("notes.txt"), None);
+    }
+
+    #[test]
+    fn mime_types_parse() {
+        let mut types = MimeTypes::new();
+
+        types.parse(
+            "# comment\n\
+             \n\
+             text/x-rst\trst rest  # trailing comment\n\
+             application/x-nothing\n\
+             image/x-icon ico\n",
+        );
+
+        assert_eq!(
+            types.lookup("a.rst").as_deref(),
+            Some("text/x-rst; charset=utf-8")
+        );
+        assert_eq!(
+            types.lookup("a.REST").as_deref(),
+            Some("text/x-rst; charset=utf-8")
+        );
+        assert_eq!(types.lookup("favicon.ico").as_deref(), Some("image/x-icon"));
+        assert_eq!(types.lookup("a.comment"), None);
+        assert_eq!(types.lookup("a.trailing"), None);
+    }
+}
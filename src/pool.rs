@@ -0,0 +1,112 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::Scope;
+
+/// What the accept loop does when every worker is busy and the queue is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Stop accepting until a slot frees up
+    #[default]
+    Block,
+    /// Answer immediately with `503 Service Unavailable`
+    Reject,
+}
+
+/// Number of jobs waiting for a free worker
+#[derive(Clone, Default)]
+pub struct Queued(Arc<AtomicUsize>);
+
+impl Queued {
+    pub fn is_empty(&self) -> bool {
+        self.0.load(Ordering::Relaxed) == 0
+    }
+}
+
+/// Fixed set of worker threads fed through a bounded queue
+pub struct WorkerPool<T> {
+    sender: SyncSender<T>,
+    backpressure: Backpressure,
+    queued: Queued,
+}
+
+impl<T: Send> WorkerPool<T> {
+    pub fn new<'scope, 'env, F>(
+        scope: &'scope Scope<'scope, 'env>,
+        workers: usize,
+        queue_size: usize,
+        backpressure: Backpressure,
+        handler: F,
+    ) -> Self
+    where
+        T: 'scope,
+        F: Fn(T, &Queued) + Send + Sync + 'scope,
+    {
+        let (sender, receiver) = mpsc::sync_channel(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+        let queued = Queued::default();
+
+        for id in 0..workers.max(1) {
+            let receiver = Arc::clone(&receiver);
+            let handler = Arc::clone(&handler);
+            let queued = queued.clone();
+
+            scope.spawn(move || Self::work(id, receiver, handler, queued));
+        }
+
+        WorkerPool {
+            sender,
+            backpressure,
+            queued,
+        }
+    }
+
+    fn work<F: Fn(T, &Queued)>(
+        id: usize,
+        receiver: Arc<Mutex<Receiver<T>>>,
+        handler: Arc<F>,
+        queued: Queued,
+    ) {
+        loop {
+            // The lock is released as soon as a job is taken
+            let job = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => break,
+            };
+
+            let Ok(job) = job else {
+                break;
+            };
+
+            queued.0.fetch_sub(1, Ordering::Relaxed);
+
+            // A panicking job must not take the worker down with it
+            if panic::catch_unwind(AssertUnwindSafe(|| handler(job, &queued))).is_err() {
+                log::error!("Worker {} recovered from a panic", id);
+            }
+        }
+
+        log::debug!("Worker {} stopped", id);
+    }
+
+    /// Queue a job. Gives it back if the pool refused it
+    pub fn dispatch(&self, job: T) -> Result<(), T> {
+        // Counted before sending so that a worker never sees it below zero
+        self.queued.0.fetch_add(1, Ordering::Relaxed);
+
+        let result = match self.backpressure {
+            Backpressure::Block => self.sender.send(job).map_err(|e| e.0),
+            Backpressure::Reject => self.sender.try_send(job).map_err(|e| match e {
+                TrySendError::Full(job) | TrySendError::Disconnected(job) => job,
+            }),
+        };
+
+        if result.is_err() {
+            self.queued.0.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        result
+    }
+}
@@ -1,11 +1,30 @@
-use crate::HTTPMethod;
+use crate::response_error::ResponseError;
+use crate::utils::find_header;
+use crate::{Extensions, HTTPMethod, Response, ResponseCode};
 use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
 
 #[derive(Clone, Debug)]
 pub struct Request {
     pub method: HTTPMethod,
+    /// Raw request target, as sent by the client
     pub url: String,
+    /// Protocol version from the request line, e.g. `HTTP/1.1`
+    pub version: String,
+    /// Decoded and normalized path part of the target, used for routing
+    pub path: String,
+    /// Decoded query parameters, all values per key
+    pub query: HashMap<String, Vec<String>>,
+    /// Path parameters captured by a var route
+    pub params: HashMap<String, String>,
     pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+    /// Application state registered with `App::with_state`
+    pub state: Arc<Extensions>,
+    /// Per-request values, e.g. inserted by middleware for handlers
+    pub extensions: Extensions,
 }
 
 impl Request {
@@ -13,9 +32,64 @@ impl Request {
         Request {
             method: HTTPMethod::default(),
             url: String::new(),
+            version: "HTTP/1.1".to_string(),
+            path: String::new(),
+            query: HashMap::new(),
+            params: HashMap::new(),
             headers: HashMap::new(),
+            body: Vec::new(),
+            state: Arc::new(Extensions::new()),
+            extensions: Extensions::new(),
         }
     }
+
+    /// First value of a query parameter
+    pub fn query_value(&self, key: &str) -> Option<&str> {
+        self.query.get(key)?.first().map(String::as_str)
+    }
+
+    /// All values of a repeated query parameter
+    pub fn query_values(&self, key: &str) -> &[String] {
+        self.query.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    /// Path parameter parsed into `T`, e.g. `req.param::<u64>("id")`
+    pub fn param<T: FromStr>(&self, name: &str) -> Result<T, ParamError> {
+        let value = self
+            .params
+            .get(name)
+            .ok_or_else(|| ParamError::Missing(name.to_string()))?;
+
+        value.parse().map_err(|_| ParamError::Invalid {
+            name: name.to_string(),
+            value: value.clone(),
+        })
+    }
+
+    /// Application state of type `T`, see `App::with_state`
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.state.get()
+    }
+
+    /// Per-request value of type `T`
+    pub fn extension<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get()
+    }
+
+    /// Case-insensitive header lookup
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Raw request body
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Request body as UTF-8 text
+    pub fn text(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.body)
+    }
 }
 
 impl Default for Request {
@@ -23,3 +97,255 @@ impl Default for Request {
         Self::new()
     }
 }
+
+#[derive(Debug)]
+pub enum ParamError {
+    /// The matched route has no such parameter
+    Missing(String),
+    /// The captured value does not parse into the requested type
+    Invalid { name: String, value: String },
+}
+
+impl ParamError {
+    pub fn response(&self) -> Response {
+        self.error_response()
+    }
+}
+
+impl std::fmt::Display for ParamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParamError::Missing(name) => write!(f, "No path parameter `{}`", name),
+            ParamError::Invalid { name, value } => {
+                write!(f, "Invalid value `{}` for path parameter `{}`", value, name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+/// First line of a request: `<method> <target> <version>`
+#[derive(Debug)]
+pub struct RequestLine {
+    pub method: HTTPMethod,
+    pub target: String,
+    pub version: String,
+}
+
+impl RequestLine {
+    pub fn parse(line: &str) -> Result<Self, RequestError> {
+        let malformed = || RequestError::BadRequest(format!("malformed request line `{}`", line));
+
+        let mut parts = line.split(' ');
+
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(malformed());
+        };
+
+        let method = HTTPMethod::try_from(method).map_err(|_| malformed())?;
+
+        if target.is_empty() {
+            return Err(malformed());
+        }
+
+        // HTTP/<major>.<minor>
+        let numbers = version
+            .strip_prefix("HTTP/")
+            .and_then(|v| v.split_once('.'))
+            .filter(|(major, minor)| {
+                [major, minor]
+                    .iter()
+                    .all(|n| n.len() == 1 && n.bytes().all(|b| b.is_ascii_digit()))
+            });
+
+        match numbers {
+            Some(("1", _)) => {}
+            Some(_) => return Err(RequestError::VersionNotSupported(version.to_string())),
+            None => return Err(malformed()),
+        }
+
+        let target = if target.starts_with('/') || target == "*" {
+            target.to_string()
+        } else if let Some((_, after_scheme)) = target.split_once("://") {
+            // Absolute form, as sent to proxies: keep the path and query only
+            match after_scheme.find(['/', '?']) {
+                Some(i) if after_scheme[i..].starts_with('/') => after_scheme[i..].to_string(),
+                Some(i) => format!("/{}", &after_scheme[i..]),
+                None => "/".to_string(),
+            }
+        } else if method == HTTPMethod::CONNECT && is_authority(target) {
+            // Authority form, only used by CONNECT: `host:port`
+            target.to_string()
+        } else {
+            return Err(malformed());
+        };
+
+        Ok(RequestLine {
+            method,
+            target,
+            version: version.to_string(),
+        })
+    }
+}
+
+/// `host:port`, with no path, query or user info
+fn is_authority(target: &str) -> bool {
+    let Some((host, port)) = target.rsplit_once(':') else {
+        return false;
+    };
+
+    !host.is_empty()
+        && !host.contains(['/', '?', '#', '@'])
+        && !port.is_empty()
+        && port.bytes().all(|b| b.is_ascii_digit())
+}
+
+#[derive(Debug)]
+pub enum RequestError {
+    Io(io::Error),
+    BadRequest(String),
+    NotImplemented(String),
+    VersionNotSupported(String),
+    /// The body is larger than the configured maximum
+    TooLarge(String),
+    UriTooLong(String),
+    HeaderTooLarge(String),
+}
+
+impl RequestError {
+    /// Response sent back to the client, if the connection is still usable
+    pub fn response(&self) -> Response {
+        match self {
+            RequestError::Io(_) => Response::error(ResponseCode::BadRequest),
+            RequestError::BadRequest(_) => Response::error(ResponseCode::BadRequest),
+            RequestError::NotImplemented(_) => Response::error(ResponseCode::NotImplemented),
+            RequestError::VersionNotSupported(_) => {
+                Response::error(ResponseCode::HTTPVersionNotSupported)
+            }
+            RequestError::TooLarge(_) => Response::error(ResponseCode::ContentTooLarge),
+            RequestError::UriTooLong(_) => Response::error(ResponseCode::URITooLong),
+            RequestError::HeaderTooLarge(_) => {
+                Response::error(ResponseCode::RequestHeaderFieldsTooLarge)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::Io(e) => write!(f, "Failed to read request: {}", e),
+            RequestError::BadRequest(reason) => write!(f, "Bad request: {}", reason),
+            RequestError::NotImplemented(reason) => write!(f, "Not implemented: {}", reason),
+            RequestError::VersionNotSupported(version) => {
+                write!(f, "HTTP version not supported: `{}`", version)
+            }
+            RequestError::TooLarge(reason) => write!(f, "Request too large: {}", reason),
+            RequestError::UriTooLong(reason) => write!(f, "Request line too long: {}", reason),
+            RequestError::HeaderTooLarge(reason) => write!(f, "Header too large: {}", reason),
+        }
+    }
+}
+
+impl From<io::Error> for RequestError {
+    fn from(value: io::Error) -> Self {
+        RequestError::Io(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(line: &str) -> Result<String, RequestError> {
+        RequestLine::parse(line).map(|line| line.target)
+    }
+
+    #[test]
+    fn parses_request_lines() {
+        let line = RequestLine::parse("GET /a/b?c=d HTTP/1.1").unwrap();
+
+        assert_eq!(line.method, HTTPMethod::GET);
+        assert_eq!(line.target, "/a/b?c=d");
+        assert_eq!(line.version, "HTTP/1.1");
+        assert_eq!(target("OPTIONS * HTTP/1.0").unwrap(), "*");
+    }
+
+    #[test]
+    fn absolute_form_keeps_path_and_query() {
+        assert_eq!(
+            target("GET http://example.com/a?b HTTP/1.1").unwrap(),
+            "/a?b"
+        );
+        assert_eq!(target("GET http://example.com?b HTTP/1.1").unwrap(), "/?b");
+        assert_eq!(
+            target("GET https://example.com:8443 HTTP/1.1").unwrap(),
+            "/"
+        );
+    }
+
+    #[test]
+    fn refuses_malformed_request_lines() {
+        for line in [
+            "",
+            "GET",
+            "GET /",
+            "GET  / HTTP/1.1",
+            "GET / HTTP/1.1 extra",
+            "GET /\tHTTP/1.1",
+            "G(T / HTTP/1.1",
+            "GET a/b HTTP/1.1",
+            "GET / HTTP/1",
+            "GET / HTTP/1.10",
+            "GET / http/1.1",
+        ] {
+            assert!(
+                matches!(target(line), Err(RequestError::BadRequest(_))),
+                "{:?}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_versions() {
+        for line in ["GET / HTTP/2.0", "GET / HTTP/0.9", "GET / HTTP/3.0"] {
+            let error = RequestLine::parse(line).err().unwrap();
+
+            assert!(
+                matches!(error, RequestError::VersionNotSupported(_)),
+                "{}",
+                line
+            );
+            assert_eq!(error.response().status, 505);
+        }
+
+        assert!(RequestLine::parse("GET / HTTP/1.0").is_ok());
+    }
+
+    #[test]
+    fn authority_form_for_connect() {
+        assert_eq!(
+            target("CONNECT example.com:443 HTTP/1.1").unwrap(),
+            "example.com:443"
+        );
+        assert_eq!(target("CONNECT [::1]:8080 HTTP/1.1").unwrap(), "[::1]:8080");
+
+        for line in [
+            "GET example.com:443 HTTP/1.1",
+            "CONNECT example.com HTTP/1.1",
+            "CONNECT :443 HTTP/1.1",
+            "CONNECT user@example.com:443 HTTP/1.1",
+            "CONNECT example.com:443/x HTTP/1.1",
+        ] {
+            assert!(
+                matches!(target(line), Err(RequestError::BadRequest(_))),
+                "{}",
+                line
+            );
+        }
+    }
+}
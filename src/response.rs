@@ -1,14 +1,18 @@
 use crate::constants::CRLF;
 use crate::header::Header;
-use crate::utils::construct_message;
-use crate::ResponseCode;
+use crate::mime::{guess_mime, sniff_mime};
+use crate::utils::{construct_message, find_header, http_date, remove_header};
+use crate::{Body, ResponseCode, ResponseError};
 use std::collections::HashMap;
-use std::fs;
+use std::fs::File;
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
 
 pub struct Response {
     pub status: u32,
     pub headers: HashMap<String, String>,
-    pub content: Vec<u8>,
+    pub body: Body,
 }
 
 impl Response {
@@ -16,7 +20,7 @@ impl Response {
         Response {
             status: ResponseCode::OK.into(),
             headers: HashMap::new(),
-            content: Vec::new(),
+            body: Body::empty(),
         }
     }
 
@@ -25,7 +29,7 @@ impl Response {
         Response {
             status: status.into(),
             headers: HashMap::new(),
-            content: Vec::new(),
+            body: Body::empty(),
         }
     }
 
@@ -33,7 +37,7 @@ impl Response {
         Response {
             status: ResponseCode::OK.into(),
             headers: HashMap::from([(Header::ContentLength.into(), content.len().to_string())]),
-            content: content.into(),
+            body: Body::from(content),
         }
     }
 
@@ -41,18 +45,77 @@ impl Response {
         Response {
             status: ResponseCode::OK.into(),
             headers: HashMap::from([(Header::ContentLength.into(), content.len().to_string())]),
-            content,
+            body: Body::from(content),
         }
     }
 
-    pub fn file(path: &str) -> Self {
-        let content_wrapped = fs::read(path);
+    /// A file with its `Content-Type` and validators, so that conditional
+    /// and range requests are answered like for static mounts. The file is
+    /// streamed, not read into memory.
+    /// `404` if it does not exist, `403` if it cannot be read
+    pub fn file(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
 
-        if let Ok(content) = content_wrapped {
-            Response::from_content_bytevec(content)
-        } else {
-            Response::from_status(ResponseCode::InternalServerError)
+        let opened = File::open(path).and_then(|file| {
+            let metadata = file.metadata()?;
+
+            match metadata.is_file() {
+                true => Ok((file, metadata)),
+                false => Err(io::ErrorKind::NotFound.into()),
+            }
+        });
+
+        let (mut file, metadata) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    log::error!("Cannot read file `{}`: {}", path.display(), e);
+                }
+
+                return e.error_response();
+            }
+        };
+
+        let type_ = guess_mime(&path.to_string_lossy()).or_else(|| Self::sniff_file(&mut file));
+
+        let mut response = Response::new().with_header(Header::AcceptRanges, "bytes");
+        response.set_body(Body::File {
+            file,
+            length: metadata.len(),
+        });
+
+        if let Some(t) = type_ {
+            response.set_header(Header::ContentType, t);
+        }
+
+        if let Ok(modified) = metadata.modified() {
+            let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+            let (secs, nanos) = (since_epoch.as_secs(), since_epoch.subsec_nanos());
+
+            // Whole seconds cannot tell apart two writes within the same second
+            let weak = if nanos == 0 { "W/" } else { "" };
+            let etag = format!("{}\"{:x}.{:x}-{:x}\"", weak, secs, nanos, metadata.len());
+
+            response.set_header(Header::LastModified, http_date(modified));
+            response.set_header(Header::ETag, etag);
         }
+
+        response
+    }
+
+    /// Type of a file from its first bytes, leaving it at its start
+    fn sniff_file(file: &mut File) -> Option<String> {
+        let mut header = Vec::new();
+        file.take(512).read_to_end(&mut header).ok()?;
+        file.rewind().ok()?;
+
+        sniff_mime(&header)
+    }
+
+    /// Body read from `reader` while it is sent, with chunked encoding
+    /// unless a `Content-Length` header is set
+    pub fn stream(reader: impl Read + Send + 'static) -> Self {
+        Response::new().with_body(Body::stream(reader))
     }
 
     pub fn html(content: impl Into<String>) -> Self {
@@ -60,9 +123,17 @@ impl Response {
     }
 
     /* Quick responses */
+    pub fn error(code: ResponseCode) -> Self {
+        let message = construct_message(format!("{:?}", code));
+        Response::from_status(code).with_content(message.into_bytes())
+    }
+
     pub fn not_found() -> Self {
-        let message = construct_message(format!("{:?}", ResponseCode::NotFound));
-        Response::from_status(ResponseCode::NotFound).with_content(message.into_bytes())
+        Response::error(ResponseCode::NotFound)
+    }
+
+    pub fn bad_request() -> Self {
+        Response::error(ResponseCode::BadRequest)
     }
 
     pub fn redirect(url: impl Into<String>) -> Self {
@@ -71,16 +142,32 @@ impl Response {
 
     /* Set */
     pub fn set_content(&mut self, content: Vec<u8>) {
-        self.set_header(Header::ContentLength, content.len().to_string());
-        self.content = content;
+        self.set_body(Body::Bytes(content));
+    }
+
+    /// Replace the body, with a `Content-Length` matching it if it is known
+    pub fn set_body(&mut self, body: impl Into<Body>) {
+        let body = body.into();
+
+        remove_header(&mut self.headers, Header::ContentLength);
+
+        if let Some(length) = body.len() {
+            self.set_header(Header::ContentLength, length.to_string());
+        }
+
+        self.body = body;
     }
 
     pub fn set_status(&mut self, status: u32) {
         self.status = status;
     }
 
+    /// Set a header, replacing any field of the same name in another casing
     pub fn set_header(&mut self, key: impl Into<String>, value: impl Into<String>) {
-        self.headers.insert(key.into(), value.into());
+        let key = key.into();
+
+        remove_header(&mut self.headers, &key);
+        self.headers.insert(key, value.into());
     }
 
     /* Inline set */
@@ -89,6 +176,11 @@ impl Response {
         self
     }
 
+    pub fn with_body(mut self, body: impl Into<Body>) -> Self {
+        self.set_body(body);
+        self
+    }
+
     pub fn with_status(mut self, status: u32) -> Self {
         self.set_status(status);
         self
@@ -100,25 +192,89 @@ impl Response {
     }
 
     /* Build */
-    pub fn build(self) -> Vec<u8> {
-        let headers_string = self
-            .headers
-            .iter()
-            .map(|(key, value)| format!("{key}: {value}"))
-            .collect::<Vec<String>>()
-            .join(CRLF);
 
+    /// Length of the body as sent, `None` if only the end of the connection
+    /// or chunked encoding can delimit it. A `Content-Length` header only
+    /// counts for a stream, other bodies know their length
+    pub(crate) fn length(&self) -> Option<u64> {
+        self.body.len().or_else(|| {
+            find_header(&self.headers, Header::ContentLength)
+                .and_then(|length| length.trim().parse().ok())
+        })
+    }
+
+    /// Write the response, reading the body as it goes
+    pub fn write_to(self, writer: &mut impl Write) -> Result<(), io::Error> {
+        self.write(writer, false, true)
+    }
+
+    /// Write the status line and headers, then the body unless `head_only`.
+    /// A body of unknown length uses chunked encoding if `chunked` is allowed,
+    /// else it runs until the connection is closed
+    pub(crate) fn write(
+        mut self,
+        writer: &mut impl Write,
+        head_only: bool,
+        chunked: bool,
+    ) -> Result<(), io::Error> {
+        // Persistent connections need every message to be framed
         let status = self.status;
+        let has_body = !(100..200).contains(&status) && status != 204 && status != 304;
+        let length = self.length();
+
+        remove_header(&mut self.headers, Header::TransferEncoding);
+
+        let use_chunked = has_body && length.is_none() && chunked;
+
+        match length {
+            Some(length) if has_body => {
+                self.set_header(Header::ContentLength, length.to_string())
+            }
+            _ if use_chunked => self.set_header(Header::TransferEncoding, "chunked"),
+            _ => {}
+        }
+
         let status_text = ResponseCode::try_from(status).map_or(String::new(), |r| format!("{:?}", r));
 
-        let response_headers = format!(
-            "HTTP/1.1 {} {}{CRLF}{}{CRLF}{CRLF}",
-            status, status_text, headers_string,
-        )
-        .into_bytes();
+        let mut head = format!("HTTP/1.1 {} {}{CRLF}", status, status_text);
+
+        for (key, value) in &self.headers {
+            head.push_str(&format!("{key}: {value}{CRLF}"));
+        }
+
+        head.push_str(CRLF);
+        writer.write_all(head.as_bytes())?;
+
+        if head_only || !has_body {
+            return writer.flush();
+        }
+
+        match (self.body, length) {
+            (body, _) if use_chunked => body.write_chunked(writer)?,
+            // A stream with a `Content-Length` set by hand is cut to it, and
+            // must fill it for the connection to stay in sync
+            (Body::Stream(reader), Some(length)) => {
+                let copied = io::copy(&mut reader.take(length), writer)?;
 
-        // Full response text
-        [response_headers, self.content].concat()
+                if copied != length {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+            (body, _) => body.write_to(writer)?,
+        }
+
+        writer.flush()
+    }
+
+    /// Full response text, with the body read into memory
+    pub fn build(self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        if let Err(e) = self.write_to(&mut bytes) {
+            log::error!("Failed to read response body: {}", e);
+        }
+
+        bytes
     }
 }
 
@@ -145,3 +301,55 @@ impl From<Vec<u8>> for Response {
         Response::from_content_bytevec(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(response: Response) -> Result<String, io::Error> {
+        let mut bytes = Vec::new();
+        response.write_to(&mut bytes)?;
+        Ok(String::from_utf8(bytes).unwrap())
+    }
+
+    #[test]
+    fn stream_with_content_length() {
+        let long = Response::stream(io::Cursor::new(b"abcdef".to_vec()))
+            .with_header(Header::ContentLength, "3");
+        let short = Response::stream(io::Cursor::new(b"abc".to_vec()))
+            .with_header(Header::ContentLength, "10");
+
+        assert!(written(long).unwrap().ends_with("\r\n\r\nabc"));
+        // Sending less than announced would desynchronize the connection
+        let error = written(short).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn stream_without_length_is_chunked() {
+        let response = written(Response::stream(io::Cursor::new(b"abc".to_vec()))).unwrap();
+
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(response.ends_with("\r\n\r\n3\r\nabc\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn known_lengths_win_over_headers() {
+        let response = Response::from("hello").with_header(Header::ContentLength, "100");
+
+        assert!(written(response).unwrap().contains("Content-Length: 5\r\n"));
+    }
+
+    #[test]
+    fn headers_are_replaced_whatever_their_casing() {
+        let response = Response::from("hello")
+            .with_header("content-length", "100")
+            .with_header("connection", "keep-alive")
+            .with_header(Header::Connection, "close");
+        let written = written(response).unwrap().to_ascii_lowercase();
+
+        assert_eq!(written.matches("content-length").count(), 1);
+        assert_eq!(written.matches("connection").count(), 1);
+        assert!(written.contains("connection: close\r\n"));
+    }
+}
@@ -21,7 +21,7 @@ macro_rules! auto_try_from__u32 {
 }
 
 auto_try_from__u32! {
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub enum ResponseCode {
         // 200
         OK = 200,
@@ -0,0 +1,75 @@
+use std::io;
+
+use crate::request::ParamError;
+use crate::{IntoResponse, Response, ResponseCode};
+
+/// An error a handler can return, turned into the response sent to the client.
+/// By default, an `InternalServerError` page
+pub trait ResponseError: std::fmt::Display {
+    fn status(&self) -> ResponseCode {
+        ResponseCode::InternalServerError
+    }
+
+    fn error_response(&self) -> Response {
+        Response::error(self.status())
+    }
+}
+
+pub type BoxedResponseError = Box<dyn ResponseError + Send + 'static>;
+
+/// What a handler may return: anything `IntoResponse`, or a `Result` of it
+pub trait HandlerResult {
+    fn into_result(self) -> Result<Response, BoxedResponseError>;
+}
+
+impl<T: IntoResponse> HandlerResult for T {
+    fn into_result(self) -> Result<Response, BoxedResponseError> {
+        Ok(self.into_response())
+    }
+}
+
+impl<T, E> HandlerResult for Result<T, E>
+where
+    T: IntoResponse,
+    E: ResponseError + Send + 'static,
+{
+    fn into_result(self) -> Result<Response, BoxedResponseError> {
+        match self {
+            Ok(value) => Ok(value.into_response()),
+            Err(error) => Err(Box::new(error)),
+        }
+    }
+}
+
+impl ResponseError for ParamError {
+    fn status(&self) -> ResponseCode {
+        match self {
+            ParamError::Missing(_) => ResponseCode::InternalServerError,
+            ParamError::Invalid { .. } => ResponseCode::BadRequest,
+        }
+    }
+}
+
+impl ResponseError for io::Error {
+    fn status(&self) -> ResponseCode {
+        match self.kind() {
+            io::ErrorKind::NotFound => ResponseCode::NotFound,
+            io::ErrorKind::PermissionDenied => ResponseCode::Forbidden,
+            _ => ResponseCode::InternalServerError,
+        }
+    }
+}
+
+impl ResponseError for std::str::Utf8Error {
+    fn status(&self) -> ResponseCode {
+        ResponseCode::BadRequest
+    }
+}
+
+impl ResponseError for std::string::FromUtf8Error {
+    fn status(&self) -> ResponseCode {
+        ResponseCode::BadRequest
+    }
+}
+
+impl ResponseError for Box<dyn std::error::Error + Send + Sync> {}
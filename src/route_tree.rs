@@ -0,0 +1,670 @@
+//! Var routes, compiled into a tree of path segments at registration time.
+//!
+//! A route matches the whole path. When several routes could match, the
+//! most specific one wins, segment by segment:
+//!
+//! 1. static segments (`/users/me`)
+//! 2. constrained parameters (`/users/{id:int}`), regexes in registration order
+//! 3. parameters capturing one segment (`/users/{id}`)
+//! 4. wildcards capturing the rest of the path (`/users/{*rest}`)
+//!
+//! Routes with the same shape and method are ambiguous and refused, and so
+//! are different built-in constraints at the same place that could both match
+//! a path, such as `{id:int}` and `{id:uint}`. Regexes cannot be compared, so
+//! the first one registered is tried first.
+
+use std::collections::{HashMap, HashSet};
+
+use regex::Regex;
+
+use crate::HTTPMethod;
+
+pub struct RouteTree<T> {
+    root: Node<T>,
+    methods: HashSet<HTTPMethod>,
+}
+
+struct Node<T> {
+    statics: HashMap<String, Node<T>>,
+    params: Vec<ParamNode<T>>,
+    wildcard: Option<Box<Node<T>>>,
+    routes: HashMap<HTTPMethod, Route<T>>,
+}
+
+struct ParamNode<T> {
+    constraint: Constraint,
+    node: Node<T>,
+}
+
+/// What a parameter accepts, written after a colon: `{id:int}`
+#[derive(Clone)]
+enum Constraint {
+    /// No constraint: word characters only
+    Word,
+    Int,
+    Uint,
+    Alpha,
+    Uuid,
+    Regex(Regex),
+}
+
+impl Constraint {
+    fn parse(source: &str) -> Result<Self, regex::Error> {
+        Ok(match source {
+            "" => Constraint::Word,
+            "int" => Constraint::Int,
+            "uint" => Constraint::Uint,
+            "alpha" => Constraint::Alpha,
+            "uuid" => Constraint::Uuid,
+            re => Constraint::Regex(Regex::new(&format!("^(?:{re})$"))?),
+        })
+    }
+
+    fn matches(&self, segment: &str) -> bool {
+        let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+        match self {
+            Constraint::Word => is_word(segment),
+            Constraint::Int => digits(segment.strip_prefix('-').unwrap_or(segment)),
+            Constraint::Uint => digits(segment),
+            Constraint::Alpha => !segment.is_empty() && segment.chars().all(char::is_alphabetic),
+            Constraint::Uuid => {
+                let groups: Vec<&str> = segment.split('-').collect();
+
+                groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12])
+                    && groups
+                        .iter()
+                        .all(|g| g.bytes().all(|b| b.is_ascii_hexdigit()))
+            }
+            Constraint::Regex(re) => re.is_match(segment),
+        }
+    }
+
+    /// Same constraint, so the same node in the tree
+    fn same_as(&self, other: &Constraint) -> bool {
+        match (self, other) {
+            (Constraint::Regex(a), Constraint::Regex(b)) => a.as_str() == b.as_str(),
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
+
+    /// Known to accept some segment in common. Different regexes are left to
+    /// registration order rather than assumed to overlap
+    fn clashes_with(&self, other: &Constraint) -> bool {
+        match (self, other) {
+            (Constraint::Regex(_), _) | (_, Constraint::Regex(_)) => self.same_as(other),
+            _ => !self.disjoint_from(other),
+        }
+    }
+
+    /// No segment can satisfy both
+    fn disjoint_from(&self, other: &Constraint) -> bool {
+        use Constraint::*;
+
+        matches!(
+            (self, other),
+            (Int | Uint | Uuid, Alpha)
+                | (Alpha, Int | Uint | Uuid)
+                | (Uuid, Int | Uint | Word)
+                | (Int | Uint | Word, Uuid)
+        )
+    }
+}
+
+struct Route<T> {
+    pattern: String,
+    param_names: Vec<String>,
+    value: T,
+}
+
+#[derive(Clone)]
+enum Segment<'a> {
+    Static(&'a str),
+    Param(&'a str, Constraint),
+    Wildcard(&'a str),
+}
+
+/// Route found for a path
+pub struct RouteMatch<'t, 'p, T> {
+    pub pattern: &'t str,
+    pub params: HashMap<&'t str, &'p str>,
+    pub value: &'t T,
+}
+
+#[derive(Debug)]
+pub enum RouteError {
+    InvalidPattern { pattern: String, reason: String },
+    Ambiguous { pattern: String, existing: String },
+}
+
+impl std::fmt::Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteError::InvalidPattern { pattern, reason } => {
+                write!(f, "Invalid route `{}`: {}", pattern, reason)
+            }
+            RouteError::Ambiguous { pattern, existing } => {
+                write!(f, "Route `{}` is ambiguous with `{}`", pattern, existing)
+            }
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn new() -> Self {
+        Node {
+            statics: HashMap::new(),
+            params: Vec::new(),
+            wildcard: None,
+            routes: HashMap::new(),
+        }
+    }
+}
+
+fn is_word(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Non-empty segments of a path, with their byte offsets
+fn split(path: &str) -> Vec<(usize, &str)> {
+    let mut offset = 0;
+    let mut segments = Vec::new();
+
+    for segment in path.split('/') {
+        if !segment.is_empty() {
+            segments.push((offset, segment));
+        }
+        offset += segment.len() + 1;
+    }
+
+    segments
+}
+
+impl<T> RouteTree<T> {
+    pub fn new() -> Self {
+        RouteTree {
+            root: Node::new(),
+            methods: HashSet::new(),
+        }
+    }
+
+    fn parse_segment<'a>(pattern: &str, segment: &'a str) -> Result<Segment<'a>, RouteError> {
+        let invalid = |reason: &str| RouteError::InvalidPattern {
+            pattern: pattern.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let Some(inner) = segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) else {
+            return match segment.contains(['{', '}']) {
+                true => Err(invalid("placeholders must span a whole segment")),
+                false => Ok(Segment::Static(segment)),
+            };
+        };
+
+        let bad_name = || invalid("placeholder names may only contain word characters");
+
+        if let Some(name) = inner.strip_prefix('*') {
+            return match is_word(name) {
+                true => Ok(Segment::Wildcard(name)),
+                false => Err(bad_name()),
+            };
+        }
+
+        let (name, constraint) = inner.split_once(':').unwrap_or((inner, ""));
+
+        if !is_word(name) {
+            return Err(bad_name());
+        }
+
+        let constraint = Constraint::parse(constraint)
+            .map_err(|e| invalid(&format!("bad constraint for `{}`: {}", name, e)))?;
+
+        Ok(Segment::Param(name, constraint))
+    }
+
+    pub fn insert(
+        &mut self,
+        method: HTTPMethod,
+        pattern: &str,
+        value: T,
+    ) -> Result<(), RouteError> {
+        let segments = split(pattern)
+            .into_iter()
+            .map(|(_, segment)| Self::parse_segment(pattern, segment))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let wildcard = segments
+            .iter()
+            .position(|s| matches!(s, Segment::Wildcard(_)));
+
+        if wildcard.is_some_and(|i| i + 1 != segments.len()) {
+            return Err(RouteError::InvalidPattern {
+                pattern: pattern.to_string(),
+                reason: "a wildcard must be the last segment".to_string(),
+            });
+        }
+
+        let ambiguous = |existing: &Route<T>| RouteError::Ambiguous {
+            pattern: pattern.to_string(),
+            existing: existing.pattern.clone(),
+        };
+
+        let mut node = &mut self.root;
+        let mut param_names = Vec::new();
+        let mut segments = segments.into_iter();
+
+        while let Some(segment) = segments.next() {
+            node = match segment {
+                Segment::Static(s) => node.statics.entry(s.to_string()).or_insert_with(Node::new),
+                Segment::Param(name, constraint) => {
+                    let rest: Vec<Segment> = segments.clone().collect();
+
+                    if let Some(existing) = Self::conflict(node, &method, &constraint, &rest) {
+                        return Err(ambiguous(existing));
+                    }
+
+                    param_names.push(name.to_string());
+                    Self::param_child(node, constraint)
+                }
+                Segment::Wildcard(name) => {
+                    param_names.push(name.to_string());
+                    node.wildcard.get_or_insert_with(|| Box::new(Node::new()))
+                }
+            };
+        }
+
+        if let Some(existing) = node.routes.get(&method) {
+            return Err(ambiguous(existing));
+        }
+
+        self.methods.insert(method.clone());
+        node.routes.insert(
+            method,
+            Route {
+                pattern: pattern.to_string(),
+                param_names,
+                value,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// A route under a different constraint at the same place that matches
+    /// some of the paths the new one would, leaving it to registration order
+    fn conflict<'t>(
+        node: &'t Node<T>,
+        method: &HTTPMethod,
+        constraint: &Constraint,
+        rest: &[Segment],
+    ) -> Option<&'t Route<T>> {
+        // Unconstrained parameters are tried last, whatever the others
+        if matches!(constraint, Constraint::Word) {
+            return None;
+        }
+
+        node.params
+            .iter()
+            .filter(|p| !matches!(p.constraint, Constraint::Word))
+            .filter(|p| !p.constraint.same_as(constraint))
+            .filter(|p| p.constraint.clashes_with(constraint))
+            .find_map(|p| Self::overlap(&p.node, method, rest))
+    }
+
+    /// A route for `method` under `node` matching some path `segments` matches
+    fn overlap<'t>(
+        node: &'t Node<T>,
+        method: &HTTPMethod,
+        segments: &[Segment],
+    ) -> Option<&'t Route<T>> {
+        let Some((segment, rest)) = segments.split_first() else {
+            return node.routes.get(method);
+        };
+
+        let wildcard = node.wildcard.as_ref().and_then(|w| w.routes.get(method));
+
+        match segment {
+            Segment::Static(s) => node
+                .statics
+                .get(*s)
+                .and_then(|child| Self::overlap(child, method, rest))
+                .or_else(|| {
+                    node.params
+                        .iter()
+                        .filter(|p| p.constraint.matches(s))
+                        .find_map(|p| Self::overlap(&p.node, method, rest))
+                })
+                .or(wildcard),
+            Segment::Param(_, constraint) => node
+                .statics
+                .iter()
+                .filter(|(s, _)| constraint.matches(s))
+                .find_map(|(_, child)| Self::overlap(child, method, rest))
+                .or_else(|| {
+                    node.params
+                        .iter()
+                        .filter(|p| p.constraint.clashes_with(constraint))
+                        .find_map(|p| Self::overlap(&p.node, method, rest))
+                })
+                .or(wildcard),
+            Segment::Wildcard(_) => Self::any_below(node, method),
+        }
+    }
+
+    /// A route for `method` at least one segment below `node`
+    fn any_below<'t>(node: &'t Node<T>, method: &HTTPMethod) -> Option<&'t Route<T>> {
+        let children = node
+            .statics
+            .values()
+            .chain(node.params.iter().map(|p| &p.node))
+            .chain(node.wildcard.as_deref());
+
+        for child in children {
+            if let Some(route) = child
+                .routes
+                .get(method)
+                .or_else(|| Self::any_below(child, method))
+            {
+                return Some(route);
+            }
+        }
+
+        None
+    }
+
+    /// Node for a parameter, keeping unconstrained parameters last
+    fn param_child(node: &mut Node<T>, constraint: Constraint) -> &mut Node<T> {
+        let position = node
+            .params
+            .iter()
+            .position(|p| p.constraint.same_as(&constraint));
+
+        let index = match position {
+            Some(index) => index,
+            None => {
+                let index = match constraint {
+                    Constraint::Word => node.params.len(),
+                    _ => node
+                        .params
+                        .iter()
+                        .take_while(|p| !matches!(p.constraint, Constraint::Word))
+                        .count(),
+                };
+
+                let child = ParamNode {
+                    constraint,
+                    node: Node::new(),
+                };
+                node.params.insert(index, child);
+                index
+            }
+        };
+
+        &mut node.params[index].node
+    }
+
+    pub fn find<'t, 'p>(
+        &'t self,
+        method: &HTTPMethod,
+        path: &'p str,
+    ) -> Option<RouteMatch<'t, 'p, T>> {
+        let segments = split(path);
+        let mut values = Vec::new();
+
+        let route = Self::find_in(&self.root, method, path, &segments, &mut values)?;
+
+        Some(RouteMatch {
+            pattern: &route.pattern,
+            params: route
+                .param_names
+                .iter()
+                .map(String::as_str)
+                .zip(values)
+                .collect(),
+            value: &route.value,
+        })
+    }
+
+    /// Whether any route uses `method`
+    pub fn has_method(&self, method: &HTTPMethod) -> bool {
+        self.methods.contains(method)
+    }
+
+    /// Methods of every route matching `path`
+    pub fn methods(&self, path: &str) -> Vec<HTTPMethod> {
+        let mut methods = Vec::new();
+        Self::methods_in(&self.root, &split(path), &mut methods);
+        methods
+    }
+
+    fn methods_in(node: &Node<T>, segments: &[(usize, &str)], methods: &mut Vec<HTTPMethod>) {
+        let Some((&(_, segment), rest)) = segments.split_first() else {
+            methods.extend(node.routes.keys().cloned());
+            return;
+        };
+
+        if let Some(child) = node.statics.get(segment) {
+            Self::methods_in(child, rest, methods);
+        }
+
+        for param in node.params.iter().filter(|p| p.constraint.matches(segment)) {
+            Self::methods_in(&param.node, rest, methods);
+        }
+
+        if let Some(wildcard) = &node.wildcard {
+            methods.extend(wildcard.routes.keys().cloned());
+        }
+    }
+
+    /// Depth-first in precedence order, backtracking on a dead end
+    fn find_in<'t, 'p>(
+        node: &'t Node<T>,
+        method: &HTTPMethod,
+        path: &'p str,
+        segments: &[(usize, &'p str)],
+        values: &mut Vec<&'p str>,
+    ) -> Option<&'t Route<T>> {
+        let Some((&(offset, segment), rest)) = segments.split_first() else {
+            return node.routes.get(method);
+        };
+
+        if let Some(child) = node.statics.get(segment) {
+            if let Some(route) = Self::find_in(child, method, path, rest, values) {
+                return Some(route);
+            }
+        }
+
+        for param in node.params.iter().filter(|p| p.constraint.matches(segment)) {
+            values.push(segment);
+
+            if let Some(route) = Self::find_in(&param.node, method, path, rest, values) {
+                return Some(route);
+            }
+
+            values.pop();
+        }
+
+        if let Some(route) = node.wildcard.as_ref().and_then(|w| w.routes.get(method)) {
+            values.push(&path[offset..]);
+            return Some(route);
+        }
+
+        None
+    }
+}
+
+impl<T> Default for RouteTree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(patterns: &[&str]) -> RouteTree<usize> {
+        let mut tree = RouteTree::new();
+
+        for (i, pattern) in patterns.iter().enumerate() {
+            tree.insert(HTTPMethod::GET, pattern, i).unwrap();
+        }
+
+        tree
+    }
+
+    /// Pattern of the route matching `path`
+    fn found<'t>(tree: &'t RouteTree<usize>, path: &str) -> Option<&'t str> {
+        tree.find(&HTTPMethod::GET, path).map(|m| m.pattern)
+    }
+
+    #[test]
+    fn captures_parameters() {
+        let tree = tree(&["/users/{id}/posts/{post}"]);
+        let matched = tree.find(&HTTPMethod::GET, "/users/42/posts/7").unwrap();
+
+        assert_eq!(matched.params["id"], "42");
+        assert_eq!(matched.params["post"], "7");
+        assert_eq!(*matched.value, 0);
+    }
+
+    #[test]
+    fn matches_whole_path() {
+        let tree = tree(&["/users/{id}"]);
+
+        assert_eq!(found(&tree, "/users/1"), Some("/users/{id}"));
+        assert_eq!(found(&tree, "/users"), None);
+        assert_eq!(found(&tree, "/users/1/posts"), None);
+        assert!(tree.find(&HTTPMethod::POST, "/users/1").is_none());
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let mut tree = RouteTree::new();
+
+        for pattern in ["/a/x{id}", "/a/{id-x}", "/a/{*rest}/b", "/a/{id:[}"] {
+            let result = tree.insert(HTTPMethod::GET, pattern, ());
+            assert!(
+                matches!(result, Err(RouteError::InvalidPattern { .. })),
+                "{}",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let tree = tree(&[
+            "/files/{*path}",
+            "/files/{name}",
+            "/files/{id:int}",
+            "/files/latest",
+        ]);
+
+        assert_eq!(found(&tree, "/files/latest"), Some("/files/latest"));
+        assert_eq!(found(&tree, "/files/12"), Some("/files/{id:int}"));
+        assert_eq!(found(&tree, "/files/report"), Some("/files/{name}"));
+        assert_eq!(found(&tree, "/files/a/b"), Some("/files/{*path}"));
+        assert_eq!(found(&tree, "/files/a.txt"), Some("/files/{*path}"));
+    }
+
+    #[test]
+    fn backtracks_on_dead_ends() {
+        let tree = tree(&["/a/static/x", "/a/{p}/y"]);
+
+        assert_eq!(found(&tree, "/a/static/y"), Some("/a/{p}/y"));
+        assert_eq!(found(&tree, "/a/static/x"), Some("/a/static/x"));
+    }
+
+    #[test]
+    fn wildcard_captures_the_rest() {
+        let tree = tree(&["/static/{*path}"]);
+        let matched = tree.find(&HTTPMethod::GET, "/static/css/site.css").unwrap();
+
+        assert_eq!(matched.params["path"], "css/site.css");
+        assert_eq!(found(&tree, "/static"), None);
+    }
+
+    #[test]
+    fn refuses_ambiguous_routes() {
+        let mut tree = tree(&["/users/{id}", "/a/{x:int}", "/b/{x:int}/c"]);
+        let ambiguous = |result| matches!(result, Err(RouteError::Ambiguous { .. }));
+
+        assert!(ambiguous(tree.insert(HTTPMethod::GET, "/users/{name}", 9)));
+        // Overlapping constraints would leave it to registration order
+        assert!(ambiguous(tree.insert(HTTPMethod::GET, "/a/{y:uint}", 9)));
+        assert!(ambiguous(tree.insert(
+            HTTPMethod::GET,
+            "/b/{y:uint}/{*rest}",
+            9
+        )));
+
+        // Other methods, disjoint constraints and paths that cannot overlap
+        assert!(tree.insert(HTTPMethod::POST, "/users/{name}", 9).is_ok());
+        assert!(tree.insert(HTTPMethod::GET, "/a/{y:alpha}", 9).is_ok());
+        assert!(tree.insert(HTTPMethod::GET, "/a/{y:uuid}", 9).is_ok());
+        assert!(tree.insert(HTTPMethod::GET, "/b/{y:uint}/d", 9).is_ok());
+        assert!(tree.insert(HTTPMethod::GET, "/a/{y}", 9).is_ok());
+    }
+
+    #[test]
+    fn regex_constraints_in_registration_order() {
+        let tree = tree(&[
+            "/p/{id:int}",
+            "/p/{slug:[a-z-]+}",
+            "/q/{a:[a-z]+}",
+            "/q/{b:[0-9]+}",
+            "/q/{c:[0-9a-z]+}",
+        ]);
+
+        assert_eq!(found(&tree, "/p/12"), Some("/p/{id:int}"));
+        assert_eq!(found(&tree, "/p/hello-world"), Some("/p/{slug:[a-z-]+}"));
+        assert_eq!(found(&tree, "/q/abc"), Some("/q/{a:[a-z]+}"));
+        assert_eq!(found(&tree, "/q/12"), Some("/q/{b:[0-9]+}"));
+        assert_eq!(found(&tree, "/q/a1"), Some("/q/{c:[0-9a-z]+}"));
+    }
+
+    #[test]
+    fn constraints() {
+        let cases = [
+            ("int", "-12", true),
+            ("int", "12", true),
+            ("int", "-", false),
+            ("int", "1.5", false),
+            ("uint", "12", true),
+            ("uint", "-12", false),
+            ("alpha", "abc\u{e9}", true),
+            ("alpha", "abc1", false),
+            ("uuid", "67e55044-10b1-426f-9247-bb680e5fe0c8", true),
+            ("uuid", "67e55044-10b1-426f-9247-bb680e5fe0c", false),
+            ("uuid", "67e55044x10b1-426f-9247-bb680e5fe0c8", false),
+            ("[a-z]+-[0-9]+", "post-12", true),
+            // Regexes are anchored
+            ("[a-z]+", "abc1", false),
+            ("", "snake_case1", true),
+            ("", "a-b", false),
+        ];
+
+        for (constraint, segment, expected) in cases {
+            let matches = Constraint::parse(constraint).unwrap().matches(segment);
+            assert_eq!(
+                matches, expected,
+                "{{x:{}}} against `{}`",
+                constraint, segment
+            );
+        }
+    }
+
+    #[test]
+    fn same_name_different_constraints() {
+        let tree = tree(&["/p/{id:uuid}", "/p/{id:int}", "/p/{slug}"]);
+
+        assert_eq!(found(&tree, "/p/5"), Some("/p/{id:int}"));
+        assert_eq!(
+            found(&tree, "/p/67e55044-10b1-426f-9247-bb680e5fe0c8"),
+            Some("/p/{id:uuid}")
+        );
+        assert_eq!(found(&tree, "/p/hello"), Some("/p/{slug}"));
+        assert_eq!(found(&tree, "/p/a-b"), None);
+    }
+}
@@ -0,0 +1,478 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::middleware::{Middleware, Next};
+use super::response_error::{BoxedResponseError, HandlerResult};
+use super::route_tree::RouteTree;
+use super::static_files::{StaticMount, StaticOptions};
+use super::{HTTPMethod, IntoResponse, Request, ResponseCode};
+
+type HandlerKey = (HTTPMethod, String);
+pub type HandlerOutput = Result<crate::Response, BoxedResponseError>;
+pub type ExactHandler = Box<dyn Fn(Request) -> HandlerOutput + Send + Sync + 'static>;
+pub type VarHandler =
+    Box<dyn Fn(Request, HashMap<&str, &str>) -> HandlerOutput + Send + Sync + 'static>;
+
+/// Shorthands over `route` and `route_var`, shared by `App` and `Router`
+macro_rules! route_shorthands {
+    () => {
+        pub fn get<HandlerFunctionLike, R>(
+            &mut self,
+            url: &str,
+            callback: HandlerFunctionLike,
+        ) -> &mut Self
+        where
+            HandlerFunctionLike: Fn(Request) -> R + Send + Sync + 'static,
+            R: HandlerResult,
+        {
+            self.route(HTTPMethod::GET, url, callback)
+        }
+
+        pub fn post<HandlerFunctionLike, R>(
+            &mut self,
+            url: &str,
+            callback: HandlerFunctionLike,
+        ) -> &mut Self
+        where
+            HandlerFunctionLike: Fn(Request) -> R + Send + Sync + 'static,
+            R: HandlerResult,
+        {
+            self.route(HTTPMethod::POST, url, callback)
+        }
+
+        pub fn put<HandlerFunctionLike, R>(
+            &mut self,
+            url: &str,
+            callback: HandlerFunctionLike,
+        ) -> &mut Self
+        where
+            HandlerFunctionLike: Fn(Request) -> R + Send + Sync + 'static,
+            R: HandlerResult,
+        {
+            self.route(HTTPMethod::PUT, url, callback)
+        }
+
+        pub fn delete<HandlerFunctionLike, R>(
+            &mut self,
+            url: &str,
+            callback: HandlerFunctionLike,
+        ) -> &mut Self
+        where
+            HandlerFunctionLike: Fn(Request) -> R + Send + Sync + 'static,
+            R: HandlerResult,
+        {
+            self.route(HTTPMethod::DELETE, url, callback)
+        }
+
+        pub fn patch<HandlerFunctionLike, R>(
+            &mut self,
+            url: &str,
+            callback: HandlerFunctionLike,
+        ) -> &mut Self
+        where
+            HandlerFunctionLike: Fn(Request) -> R + Send + Sync + 'static,
+            R: HandlerResult,
+        {
+            self.route(HTTPMethod::PATCH, url, callback)
+        }
+
+        #[track_caller]
+        pub fn get_var<VarHandlerFunctionLike, R>(
+            &mut self,
+            url: &str,
+            callback: VarHandlerFunctionLike,
+        ) -> &mut Self
+        where
+            VarHandlerFunctionLike: Fn(Request, HashMap<&str, &str>) -> R + Send + Sync + 'static,
+            R: HandlerResult,
+        {
+            self.route_var(HTTPMethod::GET, url, callback)
+        }
+
+        #[track_caller]
+        pub fn post_var<VarHandlerFunctionLike, R>(
+            &mut self,
+            url: &str,
+            callback: VarHandlerFunctionLike,
+        ) -> &mut Self
+        where
+            VarHandlerFunctionLike: Fn(Request, HashMap<&str, &str>) -> R + Send + Sync + 'static,
+            R: HandlerResult,
+        {
+            self.route_var(HTTPMethod::POST, url, callback)
+        }
+    };
+}
+
+pub(crate) use route_shorthands;
+
+/// What a matched request is answered with
+pub enum Endpoint<'r> {
+    Exact(&'r ExactHandler),
+    Var(&'r VarHandler),
+    File(PathBuf),
+    /// A static directory requested without its trailing slash
+    AddSlash,
+    Refused(ResponseCode),
+}
+
+/// Result of routing a path through a router and its mounts
+pub struct Matched<'r> {
+    /// Route pattern, including mount prefixes
+    pub route: String,
+    /// Path relative to the router owning the endpoint
+    pub path: String,
+    pub params: HashMap<String, String>,
+    pub endpoint: Endpoint<'r>,
+    /// Middleware of the routers on the way, outermost first
+    pub middlewares: Vec<&'r Middleware>,
+}
+
+/// A set of routes that can be built on its own and mounted under a prefix
+/// with `App::mount` or `Router::mount`. Handlers see the path with the
+/// prefix stripped
+pub struct Router {
+    exact_handlers: HashMap<HandlerKey, ExactHandler>,
+    var_handlers: RouteTree<VarHandler>,
+    static_handlers: HashMap<String, StaticMount>,
+    mounts: Vec<(String, Router)>,
+    middlewares: Vec<Middleware>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self {
+            exact_handlers: HashMap::new(),
+            var_handlers: RouteTree::new(),
+            static_handlers: HashMap::new(),
+            mounts: Vec::new(),
+            middlewares: Vec::new(),
+        }
+    }
+
+    /// Route for an exact path. Method names are case-sensitive, `"GET"`, not `"get"`.
+    /// Handlers return anything `IntoResponse`, or a `Result` of it whose error
+    /// implements `ResponseError`.
+    /// Panics if the method is not a valid token
+    #[track_caller]
+    pub fn route<HTTPMethodLike, HandlerFunctionLike, R>(
+        &mut self,
+        method: HTTPMethodLike,
+        url: &str,
+        callback: HandlerFunctionLike,
+    ) -> &mut Self
+    where
+        HTTPMethodLike: TryInto<HTTPMethod> + std::fmt::Debug,
+        HandlerFunctionLike: Fn(Request) -> R + Send + Sync + 'static,
+        R: HandlerResult,
+        <HTTPMethodLike as TryInto<HTTPMethod>>::Error: std::fmt::Display,
+    {
+        let boxed_cb: ExactHandler = Box::new(move |req| callback(req).into_result());
+        let http_method: HTTPMethod = match method.try_into() {
+            Ok(method) => method,
+            Err(e) => panic!("{}", e),
+        };
+
+        if let HTTPMethod::Extension(m) = &http_method {
+            let standard = HTTPMethod::try_from(m.to_uppercase())
+                .is_ok_and(|upper| !matches!(upper, HTTPMethod::Extension(_)));
+
+            if standard {
+                log::warn!(
+                    "Methods are case-sensitive, `{}` is not `{}`",
+                    m,
+                    m.to_uppercase()
+                );
+            }
+        }
+
+        let key: (HTTPMethod, String) = (http_method, url.to_owned());
+
+        self.exact_handlers.insert(key, boxed_cb);
+
+        self
+    }
+
+    /// Route with `{name}` placeholders capturing one path segment and an
+    /// optional trailing `{*name}` wildcard capturing the rest of the path.
+    /// Placeholders take an optional constraint: `{id:int}`, `{id:uint}`,
+    /// `{name:alpha}`, `{id:uuid}` or a regex such as `{slug:[a-z0-9-]+}`.
+    /// Captured values are also available typed through `Request::param`.
+    ///
+    /// Routes match the whole path. Exact routes win over var routes; between
+    /// var routes, static segments beat constrained placeholders, which beat
+    /// plain placeholders, which beat wildcards.
+    /// Panics if the pattern is invalid or ambiguous with an existing route
+    #[track_caller]
+    pub fn route_var<HTTPMethodLike, VarHandlerFunctionLike, R>(
+        &mut self,
+        method: HTTPMethodLike,
+        url: &str,
+        callback: VarHandlerFunctionLike,
+    ) -> &mut Self
+    where
+        HTTPMethodLike: Into<HTTPMethod>,
+        VarHandlerFunctionLike: Fn(Request, HashMap<&str, &str>) -> R + Send + Sync + 'static,
+        R: HandlerResult,
+    {
+        let boxed_cb: VarHandler = Box::new(move |req, vars| callback(req, vars).into_result());
+
+        // Patterns are compiled here, so a broken one is a programming error
+        if let Err(e) = self.var_handlers.insert(method.into(), url, boxed_cb) {
+            panic!("{}", e);
+        }
+
+        self
+    }
+
+    // Shorthands
+
+    route_shorthands!();
+
+    pub fn static_(&mut self, url: &str, dest: &str) -> &mut Self {
+        self.static_with(url, dest, StaticOptions::default())
+    }
+
+    pub fn static_with(&mut self, url: &str, dest: &str, options: StaticOptions) -> &mut Self {
+        self.static_handlers
+            .insert(Self::normalize_prefix(url), StaticMount::new(dest, options));
+        self
+    }
+
+    /// Serve `router` under `prefix`. Routes of this router are tried first,
+    /// then mounts from the longest prefix to the shortest
+    pub fn mount(&mut self, prefix: &str, router: Router) -> &mut Self {
+        self.mounts.push((Self::normalize_prefix(prefix), router));
+        self.mounts
+            .sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        self
+    }
+
+    /// Run `callback` around every endpoint of this router and its mounts,
+    /// static files included. Middleware runs in registration order, each
+    /// calling `next.run(request)` to continue, or answering on its own
+    pub fn middleware<MiddlewareFunctionLike, R>(
+        &mut self,
+        callback: MiddlewareFunctionLike,
+    ) -> &mut Self
+    where
+        MiddlewareFunctionLike: Fn(Request, Next) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.middlewares.push(Box::new(move |req, next| {
+            callback(req, next).into_response()
+        }));
+        self
+    }
+
+    fn normalize_prefix(url: &str) -> String {
+        let mut url_ = url.to_string();
+
+        url_ = url_.trim_end_matches('/').to_string();
+
+        if !url_.starts_with('/') {
+            url_ = format!("/{url_}");
+        }
+
+        url_
+    }
+
+    /// Path below a mount prefix, if `path` is inside it
+    fn strip_mount_prefix<'p>(path: &'p str, prefix: &str) -> Option<&'p str> {
+        if prefix == "/" {
+            return Some(path);
+        }
+
+        match path.strip_prefix(prefix)? {
+            "" => Some("/"),
+            rest if rest.starts_with('/') => Some(rest),
+            _ => None,
+        }
+    }
+
+    // Lookup
+
+    pub(crate) fn find(&self, method: &HTTPMethod, path: &str) -> Option<Matched<'_>> {
+        let mut matched = self
+            .find_exact(method, path)
+            .or_else(|| self.find_var(method, path))
+            .or_else(|| self.find_static(method, path))
+            .or_else(|| self.find_mounted(method, path))?;
+
+        matched.middlewares.splice(0..0, &self.middlewares);
+        Some(matched)
+    }
+
+    fn find_exact(&self, method: &HTTPMethod, path: &str) -> Option<Matched<'_>> {
+        let handler = self
+            .exact_handlers
+            .get(&(method.clone(), path.to_string()))?;
+
+        Some(Matched {
+            route: path.to_string(),
+            path: path.to_string(),
+            params: HashMap::new(),
+            endpoint: Endpoint::Exact(handler),
+            middlewares: Vec::new(),
+        })
+    }
+
+    fn find_var(&self, method: &HTTPMethod, path: &str) -> Option<Matched<'_>> {
+        let found = self.var_handlers.find(method, path)?;
+
+        log::debug!("Found var handler: {}", found.pattern);
+
+        Some(Matched {
+            route: found.pattern.to_string(),
+            path: path.to_string(),
+            params: found
+                .params
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            endpoint: Endpoint::Var(found.value),
+            middlewares: Vec::new(),
+        })
+    }
+
+    fn find_mounted(&self, method: &HTTPMethod, path: &str) -> Option<Matched<'_>> {
+        self.mounts.iter().find_map(|(prefix, router)| {
+            let inner_path = Self::strip_mount_prefix(path, prefix)?;
+            let mut matched = router.find(method, inner_path)?;
+
+            matched.route = format!("{}{}", prefix.trim_end_matches('/'), matched.route);
+            Some(matched)
+        })
+    }
+
+    fn url_starts_with(url: String, key: String) -> bool {
+        let url_seg = url.split("/").collect::<Vec<_>>();
+        let key_seg = key.split("/").collect::<Vec<_>>();
+        url_seg.starts_with(&key_seg) || key == "/"
+    }
+
+    fn similarity(url: String, key: String) -> i32 {
+        let url_seg = url.split("/");
+        let key_seg = key.split("/");
+
+        url_seg.zip(key_seg).take_while(|(u, k)| u == k).count() as i32
+    }
+
+    /// Most specific static mount for a path, with the path relative to it
+    fn find_static_mount<'a>(
+        &'a self,
+        url: &'a str,
+    ) -> Option<(&'a str, &'a StaticMount, &'a str)> {
+        let keys = self.static_handlers.keys();
+
+        // Find keys for current path
+        let mut keys: Vec<&String> = keys
+            .filter(|k| Self::url_starts_with(url.to_string(), k.to_string()))
+            .collect();
+
+        if keys.is_empty() {
+            return None;
+        }
+
+        keys.sort_by_key(|k| Self::similarity(url.to_string(), k.to_string()));
+
+        let selected = keys.last().unwrap();
+        let mount = self.static_handlers.get(*selected)?;
+
+        let relative = url.strip_prefix(selected.as_str()).unwrap_or(url);
+
+        Some((selected, mount, relative))
+    }
+
+    fn find_static(&self, method: &HTTPMethod, url: &str) -> Option<Matched<'_>> {
+        if *method != HTTPMethod::GET {
+            return None;
+        }
+
+        let (selected, mount, relative) = self.find_static_mount(url)?;
+
+        let matched = |endpoint| {
+            Some(Matched {
+                route: selected.to_string(),
+                path: url.to_string(),
+                params: HashMap::new(),
+                endpoint,
+                middlewares: Vec::new(),
+            })
+        };
+
+        let resource_path = match mount.resolve(relative) {
+            Ok(path) => path,
+            Err(ResponseCode::NotFound) => return None,
+            Err(code) => return matched(Endpoint::Refused(code)),
+        };
+
+        log::debug!(
+            "Found static resource path `{}` on handler `{}`",
+            resource_path.display(),
+            selected
+        );
+
+        if resource_path.is_file() {
+            return matched(Endpoint::File(resource_path));
+        }
+
+        if resource_path.is_dir() {
+            if !url.ends_with('/') {
+                log::debug!("Adding a slash to `{}` as it is a path", url);
+                return matched(Endpoint::AddSlash);
+            }
+
+            return match mount.resolve(&format!("{relative}/index.html")) {
+                Ok(index_path) if index_path.is_file() => matched(Endpoint::File(index_path)),
+                Ok(_) | Err(ResponseCode::NotFound) => None,
+                Err(code) => matched(Endpoint::Refused(code)),
+            };
+        }
+
+        None
+    }
+
+    /// Methods for which some handler or static mount answers `path`
+    pub(crate) fn allowed_methods(&self, path: &str) -> Vec<HTTPMethod> {
+        let mut methods: Vec<HTTPMethod> = self
+            .exact_handlers
+            .keys()
+            .filter(|(_, url)| url == path)
+            .map(|(method, _)| method.clone())
+            .chain(self.var_handlers.methods(path))
+            .collect();
+
+        let static_found = self
+            .find_static_mount(path)
+            .is_some_and(|(_, mount, relative)| mount.resolve(relative).is_ok());
+
+        if static_found {
+            methods.push(HTTPMethod::GET);
+        }
+
+        for (prefix, router) in &self.mounts {
+            if let Some(inner_path) = Self::strip_mount_prefix(path, prefix) {
+                methods.extend(router.allowed_methods(inner_path));
+            }
+        }
+
+        methods
+    }
+
+    /// Whether any route of this router or its mounts uses `method`
+    pub(crate) fn implements(&self, method: &HTTPMethod) -> bool {
+        self.exact_handlers.keys().any(|(m, _)| m == method)
+            || self.var_handlers.has_method(method)
+            || self
+                .mounts
+                .iter()
+                .any(|(_, router)| router.implements(method))
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
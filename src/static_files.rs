@@ -0,0 +1,156 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::ResponseCode;
+
+#[derive(Clone, Debug)]
+pub struct StaticOptions {
+    refuse_outside_symlinks: bool,
+}
+
+impl StaticOptions {
+    pub fn new() -> Self {
+        StaticOptions {
+            refuse_outside_symlinks: true,
+        }
+    }
+
+    /// Refuse to serve symlinks resolving outside of the mounted directory (default: `true`)
+    pub fn with_refuse_outside_symlinks(mut self, refuse: bool) -> Self {
+        self.refuse_outside_symlinks = refuse;
+        self
+    }
+}
+
+impl Default for StaticOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct StaticMount {
+    pub dest: PathBuf,
+    pub options: StaticOptions,
+}
+
+impl StaticMount {
+    pub fn new(dest: &str, options: StaticOptions) -> Self {
+        StaticMount {
+            dest: PathBuf::from(dest),
+            options,
+        }
+    }
+
+    /// Map a path relative to the mount onto the filesystem.
+    /// Fails with `NotFound` for missing files and `Forbidden` for anything outside of `dest`
+    pub fn resolve(&self, relative: &str) -> Result<PathBuf, ResponseCode> {
+        let mut path = self.dest.clone();
+
+        for segment in relative.split('/').filter(|s| !s.is_empty()) {
+            let mut components = Path::new(segment).components();
+
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) => path.push(segment),
+                _ => {
+                    log::warn!("Refusing static path segment `{}`", segment);
+                    return Err(ResponseCode::Forbidden);
+                }
+            }
+        }
+
+        if !path.exists() {
+            return Err(ResponseCode::NotFound);
+        }
+
+        if self.options.refuse_outside_symlinks {
+            let root = fs::canonicalize(&self.dest).map_err(|_| ResponseCode::NotFound)?;
+            let real = fs::canonicalize(&path).map_err(|_| ResponseCode::NotFound)?;
+
+            if !real.starts_with(&root) {
+                log::warn!(
+                    "Refusing `{}`: resolves to `{}` outside of `{}`",
+                    path.display(),
+                    real.display(),
+                    root.display()
+                );
+                return Err(ResponseCode::Forbidden);
+            }
+        }
+
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mounted `root` with `root/inner.txt`, next to `outside.txt`
+    fn fixture(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("saaba-{}-{}", name, std::process::id()));
+
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(dir.join("root/sub")).unwrap();
+        fs::write(dir.join("root/inner.txt"), "inner").unwrap();
+        fs::write(dir.join("outside.txt"), "outside").unwrap();
+        dir
+    }
+
+    fn mount(dir: &Path, options: StaticOptions) -> StaticMount {
+        StaticMount::new(dir.join("root").to_str().unwrap(), options)
+    }
+
+    #[test]
+    fn resolves_inside_the_root() {
+        let dir = fixture("static-inside");
+        let mount = mount(&dir, StaticOptions::default());
+
+        assert_eq!(mount.resolve("inner.txt"), Ok(dir.join("root/inner.txt")));
+        assert_eq!(mount.resolve("/sub/"), Ok(dir.join("root/sub")));
+        assert_eq!(mount.resolve("missing.txt"), Err(ResponseCode::NotFound));
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn refuses_traversal() {
+        let dir = fixture("static-traversal");
+        let mount = mount(&dir, StaticOptions::default());
+
+        for relative in [
+            "..",
+            "../outside.txt",
+            "sub/../../outside.txt",
+            "./inner.txt",
+        ] {
+            assert_eq!(
+                mount.resolve(relative),
+                Err(ResponseCode::Forbidden),
+                "{}",
+                relative
+            );
+        }
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_leaving_the_root() {
+        let dir = fixture("static-symlink");
+        std::os::unix::fs::symlink(dir.join("outside.txt"), dir.join("root/out")).unwrap();
+        std::os::unix::fs::symlink(dir.join("root/inner.txt"), dir.join("root/in")).unwrap();
+
+        let strict = mount(&dir, StaticOptions::default());
+        let lenient = mount(
+            &dir,
+            StaticOptions::new().with_refuse_outside_symlinks(false),
+        );
+
+        assert_eq!(strict.resolve("out"), Err(ResponseCode::Forbidden));
+        assert_eq!(strict.resolve("in"), Ok(dir.join("root/in")));
+        assert_eq!(lenient.resolve("out"), Ok(dir.join("root/out")));
+
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -1,22 +1,165 @@
 use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use regex::Regex;
+use crate::header::Header;
+use crate::request::RequestError;
 
-pub fn parse_headers(headers_vec: Vec<String>) -> HashMap<String, String> {
-    let mut headers = HashMap::new();
-    let header_re = Regex::new(r"(?<key>[a-zA-Z-_]+):\s?(?<value>.+)").unwrap();
+/// Parse header lines as `name ":" OWS value OWS`. Names are matched
+/// case-insensitively: a repeated field is joined with `, ` under its first
+/// spelling, and differing `Content-Length` values are refused
+pub fn parse_headers(lines: Vec<String>) -> Result<HashMap<String, String>, RequestError> {
+    let mut headers: HashMap<String, String> = HashMap::new();
 
-    for line in headers_vec.into_iter() {
-        let capture_opt = header_re.captures(line.as_str());
+    for line in lines.iter() {
+        let (name, value) = parse_header(line)
+            .ok_or_else(|| RequestError::BadRequest(format!("invalid header line `{}`", line)))?;
 
-        if let Some(header) = capture_opt {
-            headers.insert(header["key"].into(), header["value"].into());
-        } else {
-            log::error!("Failed to parse header: {}", line);
+        let existing = headers
+            .iter_mut()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name));
+
+        match existing {
+            None => {
+                headers.insert(name.to_string(), value.to_string());
+            }
+            Some((_, existing)) if name.eq_ignore_ascii_case(Header::ContentLength) => {
+                if existing != value {
+                    return Err(RequestError::BadRequest(
+                        "conflicting Content-Length headers".to_string(),
+                    ));
+                }
+            }
+            Some((_, existing)) => {
+                existing.push_str(", ");
+                existing.push_str(value);
+            }
+        }
+    }
+
+    Ok(headers)
+}
+
+/// Split a header line into its name and value, `None` if it is malformed
+fn parse_header(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once(':')?;
+
+    let is_tchar = |b: u8| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b);
+
+    // No whitespace is allowed between the name and the colon
+    if name.is_empty() || !name.bytes().all(is_tchar) {
+        return None;
+    }
+
+    let value = value.trim_matches([' ', '\t']);
+
+    match value.bytes().any(|b| b.is_ascii_control() && b != b'\t') {
+        true => None,
+        false => Some((name, value)),
+    }
+}
+
+/// Decode `%XX` escapes, leaving malformed ones as they are
+pub fn percent_decode(input: &str) -> Vec<u8> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap();
+            decoded.push(u8::from_str_radix(hex, 16).unwrap());
+            i += 3;
+            continue;
+        }
+
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    decoded
+}
+
+/// Encode everything but unreserved characters, so that a decoded path
+/// segment can go back into a URL
+pub fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+
+    for b in segment.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    encoded
+}
+
+/// Resolve `.`, `..` and empty segments of a request path, decoding each
+/// segment after splitting. Returns `None` if a segment does not decode to
+/// valid UTF-8, or decodes to a NUL or a `/`
+pub fn normalize_path(raw: &str) -> Option<String> {
+    let mut segments: Vec<String> = Vec::new();
+    let mut trailing_slash = false;
+
+    for segment in raw.split('/').skip_while(|s| s.is_empty()) {
+        let segment = String::from_utf8(percent_decode(segment)).ok()?;
+
+        // An encoded slash would otherwise become a separator when routing
+        if segment.contains(['\0', '/']) {
+            return None;
         }
+
+        trailing_slash = true;
+
+        match segment.as_str() {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => {
+                segments.push(segment);
+                trailing_slash = false;
+            }
+        }
+    }
+
+    let mut path = format!("/{}", segments.join("/"));
+
+    if trailing_slash && !segments.is_empty() {
+        path.push('/');
     }
 
+    Some(path)
+}
+
+/// Parse `a=1&b=2&a=3` into a map of all values per key
+pub fn parse_query(query: &str) -> HashMap<String, Vec<String>> {
+    let mut params: HashMap<String, Vec<String>> = HashMap::new();
+
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+        let decode =
+            |s: &str| String::from_utf8_lossy(&percent_decode(&s.replace('+', " "))).into_owned();
+
+        params.entry(decode(key)).or_default().push(decode(value));
+    }
+
+    params
+}
+
+/// Case-insensitive lookup in a header map
+pub fn find_header<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
     headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
 }
 
 pub fn construct_message(message: String) -> String {
@@ -29,3 +172,265 @@ pub fn construct_message(message: String) -> String {
         message
     )
 }
+
+/// Remove a header whatever its case
+pub fn remove_header(headers: &mut HashMap<String, String>, name: &str) {
+    headers.retain(|key, _| !key.eq_ignore_ascii_case(name));
+}
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Days since 1970-01-01 for a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// `(year, month, day)` for a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// Format a time as an HTTP date: `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn http_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) as i64;
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Parse an HTTP date in the IMF-fixdate format produced by `http_date`
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let (_, date) = date.trim().split_once(", ")?;
+
+    let [day, month, year, time, "GMT"] = date.split(' ').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let (day, year): (i64, i64) = (day.parse().ok()?, year.parse().ok()?);
+
+    let [hours, minutes, seconds] = time
+        .split(':')
+        .map(|n| n.parse::<i64>().ok())
+        .collect::<Option<Vec<_>>>()?[..]
+    else {
+        return None;
+    };
+
+    if !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds;
+
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_values() {
+        let query = parse_query("a=1&b=two+words&a=3&flag&&c=%2Fx%3D");
+
+        assert_eq!(query["a"], ["1", "3"]);
+        assert_eq!(query["b"], ["two words"]);
+        assert_eq!(query["flag"], [""]);
+        assert_eq!(query["c"], ["/x="]);
+        assert_eq!(query.len(), 4);
+    }
+
+    #[test]
+    fn query_encoded_keys() {
+        let query = parse_query("na%20me=v&plus=%2B&bad=%zz%4");
+
+        assert_eq!(query["na me"], ["v"]);
+        assert_eq!(query["plus"], ["+"]);
+        assert_eq!(query["bad"], ["%zz%4"]);
+        assert!(parse_query("").is_empty());
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        let cases = [
+            ("/", "/"),
+            ("", "/"),
+            ("//a///b", "/a/b"),
+            ("/a/./b/../c", "/a/c"),
+            ("/../../a", "/a"),
+            ("/a/b/", "/a/b/"),
+            ("/a/b/..", "/a/"),
+            ("/a/%2e%2E/b", "/b"),
+            ("/caf%C3%A9/a%20b", "/caf\u{e9}/a b"),
+            ("/100%", "/100%"),
+        ];
+
+        for (raw, expected) in cases {
+            assert_eq!(normalize_path(raw).as_deref(), Some(expected), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn refuses_undecodable_paths() {
+        // An encoded slash is refused rather than turned into a separator
+        assert_eq!(normalize_path("/a%2Fb"), None);
+        assert_eq!(normalize_path("/..%2f..%2fetc"), None);
+        assert_eq!(normalize_path("/a%00"), None);
+        assert_eq!(normalize_path("/%ff"), None);
+    }
+
+    fn headers(lines: &[&str]) -> Result<HashMap<String, String>, RequestError> {
+        parse_headers(lines.iter().map(|l| l.to_string()).collect())
+    }
+
+    #[test]
+    fn parses_header_lines() {
+        let headers = headers(&[
+            "Host: example.com",
+            "X-Empty:",
+            "x-padded: \t value \t",
+            "Accept: text/html",
+            "accept: */*",
+            "Content-Length: 3",
+            "content-length: 3",
+        ])
+        .unwrap();
+
+        assert_eq!(find_header(&headers, "host"), Some("example.com"));
+        assert_eq!(find_header(&headers, "X-Empty"), Some(""));
+        assert_eq!(find_header(&headers, "X-Padded"), Some("value"));
+        assert_eq!(headers["Accept"], "text/html, */*");
+        assert_eq!(headers["Content-Length"], "3");
+        assert_eq!(headers.len(), 5);
+    }
+
+    #[test]
+    fn refuses_malformed_header_lines() {
+        let lines = [
+            "Content-Length : 3",
+            "Content-Length\t: 3",
+            " folded",
+            "no colon",
+            ": no name",
+            "Bad Name: x",
+            "X-Ctl: a\x01b",
+        ];
+
+        for line in lines {
+            assert!(
+                matches!(headers(&[line]), Err(RequestError::BadRequest(_))),
+                "{:?}",
+                line
+            );
+        }
+
+        let conflicting = headers(&["Content-Length: 3", "content-length: 10"]);
+        assert!(matches!(conflicting, Err(RequestError::BadRequest(_))));
+    }
+
+    #[test]
+    fn header_names_are_whole_tokens() {
+        let headers = headers(&["X2Transfer-Encoding: chunked"]).unwrap();
+
+        assert_eq!(find_header(&headers, "Transfer-Encoding"), None);
+    }
+
+    #[test]
+    fn formats_http_dates() {
+        let date = UNIX_EPOCH + Duration::from_secs(784111777);
+
+        assert_eq!(http_date(date), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(
+            http_date(UNIX_EPOCH + Duration::from_secs(951782400)),
+            "Tue, 29 Feb 2000 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn parses_http_dates() {
+        let date = UNIX_EPOCH + Duration::from_secs(784111777);
+
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(date));
+        assert_eq!(
+            parse_http_date(" Sun, 06 Nov 1994 08:49:37 GMT "),
+            Some(date)
+        );
+
+        for invalid in [
+            "",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1960 08:49:37 GMT",
+        ] {
+            assert_eq!(parse_http_date(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn http_dates_round_trip() {
+        for secs in [
+            0,
+            59,
+            86399,
+            86400,
+            1_000_000_000,
+            1_700_000_000,
+            4_102_444_800,
+        ] {
+            let date = UNIX_EPOCH + Duration::from_secs(secs);
+            assert_eq!(parse_http_date(&http_date(date)), Some(date));
+        }
+    }
+
+    #[test]
+    fn encodes_segments() {
+        assert_eq!(percent_encode("sub dir"), "sub%20dir");
+        assert_eq!(percent_encode("caf\u{e9}"), "caf%C3%A9");
+        assert_eq!(percent_encode("a:b?c#d%"), "a%3Ab%3Fc%23d%25");
+        assert_eq!(percent_encode("file-1.tar_gz~"), "file-1.tar_gz~");
+        assert_eq!(
+            normalize_path(&format!("/{}", percent_encode("a b/c"))),
+            None
+        );
+    }
+}
@@ -2,12 +2,17 @@
 "gz" => "application/gzip",
 "jar" => "application/java-archive",
 "json" => "application/json",
+"map" => "application/json",
 "jsonld" => "application/ld+json",
+"webmanifest" => "application/manifest+json",
 "doc" => "application/msword",
 "bin" => "application/octet-stream",
 "ogx" => "application/ogg",
 "pdf" => "application/pdf",
 "rtf" => "application/rtf",
+"atom" => "application/atom+xml",
+"rss" => "application/rss+xml",
+"toml" => "application/toml",
 "azw" => "application/vnd.amazon.ebook",
 "mpkg" => "application/vnd.apple.installer+xml",
 "xul" => "application/vnd.mozilla.xul+xml",
@@ -22,6 +27,7 @@
 "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
 "rar" => "application/vnd.rar",
 "vsd" => "application/vnd.visio",
+"wasm" => "application/wasm",
 "7z" => "application/x-7z-compressed",
 "abw" => "application/x-abiword",
 "bz" => "application/x-bzip",
@@ -32,17 +38,25 @@
 "php" => "application/x-httpd-php",
 "sh" => "application/x-sh",
 "tar" => "application/x-tar",
+"xz" => "application/x-xz",
 "xhtml" => "application/xhtml+xml",
 "xml" => "application/xml",
+"yaml" => "application/yaml",
+"yml" => "application/yaml",
 "zip" => "application/zip",
+"zst" => "application/zstd",
 "aac" => "audio/aac",
+"flac" => "audio/flac",
 "mid" => "audio/midi",
 "midi" => "audio/midi",
+"m4a" => "audio/mp4",
 "mp3" => "audio/mpeg",
 "oga" => "audio/ogg",
+"ogg" => "audio/ogg",
 "opus" => "audio/ogg",
 "wav" => "audio/wav",
 "weba" => "audio/webm",
+"ttc" => "font/collection",
 "otf" => "font/otf",
 "ttf" => "font/ttf",
 "woff" => "font/woff",
@@ -51,10 +65,15 @@
 "avif" => "image/avif",
 "bmp" => "image/bmp",
 "gif" => "image/gif",
+"heic" => "image/heic",
+"heif" => "image/heif",
 "jpeg" => "image/jpeg",
 "jpg" => "image/jpeg",
+"jxl" => "image/jxl",
 "png" => "image/png",
 "svg" => "image/svg+xml",
+"tif" => "image/tiff",
+"tiff" => "image/tiff",
 "ico" => "image/vnd.microsoft.icon",
 "webp" => "image/webp",
 "ics" => "text/calendar",
@@ -62,15 +81,22 @@
 "csv" => "text/csv",
 "htm" => "text/html",
 "html" => "text/html",
-"tif" => "text/html",
-"tiff" => "text/htmlts video/mp2t",
+"cjs" => "text/javascript",
 "js" => "text/javascript",
 "mjs" => "text/javascript",
+"md" => "text/markdown",
 "txt" => "text/plain",
+"tsv" => "text/tab-separated-values",
+"vtt" => "text/vtt",
 "3gp" => "video/3gpp",
 "3g2" => "video/3gpp2",
+"ts" => "video/mp2t",
+"m4v" => "video/mp4",
 "mp4" => "video/mp4",
 "mpeg" => "video/mpeg",
+"mpg" => "video/mpeg",
 "ogv" => "video/ogg",
+"mov" => "video/quicktime",
 "webm" => "video/webm",
+"mkv" => "video/x-matroska",
 "avi" => "video/x-msvideo",
\ No newline at end of file
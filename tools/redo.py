@@ -10,6 +10,9 @@ with Path(root / "mime_types.txt").open() as f:
     lines = f.read().split("\n")
 
     for line in lines:
+        if not line.strip():
+            continue
+
         ext, mime = line.split(maxsplit=1)
         res.append(f'"{ext}" => "{mime}",')
 